@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pydepsync::engine::{DetectEngine, EngineOptions};
+use pydepsync::pyproject;
+
+const PACKAGES: usize = 100;
+const MODULES_PER_PACKAGE: usize = 100;
+
+// Lay out a synthetic repo of PACKAGES * MODULES_PER_PACKAGE python files that
+// only import from the stdlib and each other, so nothing needs resolving
+fn synthetic_repo(root: &Path) {
+    fs::write(
+        root.join("pyproject.toml"),
+        "[project]\nname = \"bench\"\nversion = \"0.1.0\"\ndependencies = []\n",
+    )
+    .unwrap();
+    for p in 0..PACKAGES {
+        let package = root.join(format!("pkg_{p}"));
+        fs::create_dir_all(&package).unwrap();
+        fs::write(package.join("__init__.py"), "").unwrap();
+        for m in 0..MODULES_PER_PACKAGE {
+            let sibling = (p + m) % PACKAGES;
+            let source = format!(
+                "import os\nimport json\nfrom collections import defaultdict\n\
+                 from pkg_{sibling} import module_{m}\n\n\
+                 def handler_{m}(event):\n    counts = defaultdict(int)\n    \
+                 for key in json.loads(event):\n        counts[key] += 1\n    \
+                 return os.path.join(*counts)\n"
+            );
+            fs::write(package.join(format!("module_{m}.py")), source).unwrap();
+        }
+    }
+}
+
+fn bench_detect_dependencies(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    synthetic_repo(&root);
+    let pyproject = pyproject::read(&root.join("pyproject.toml")).unwrap();
+    let engine = DetectEngine::new(pyproject, EngineOptions::default()).unwrap();
+
+    let mut group = c.benchmark_group("scan");
+    group.sample_size(10);
+    group.bench_function("detect_dependencies_10k_files", |b| {
+        b.iter(|| engine.detect_dependencies(PathBuf::from(&root)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_detect_dependencies);
+criterion_main!(benches);
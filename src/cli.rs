@@ -1,21 +1,113 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+
+use crate::engine::VersionStrategy;
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Add missing dependencies to pyproject.toml
+    Pyproject,
+    /// Write every dependency, existing and new, to a requirements.txt
+    Requirements,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    /// Log lines, and the missing dependencies one per line
+    Text,
+    /// A JSON report of the run on stdout, logs stay on stderr
+    Json,
+}
+
+#[derive(PartialEq, Subcommand, Debug)]
+pub enum Command {
+    /// Print every import the parser finds in a single python file, for troubleshooting detection
+    DebugImports {
+        /// The python file to parse
+        path: PathBuf,
+    },
+}
 
 #[derive(PartialEq, Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// List of directories to ignore, we ignore .venv and .git by default
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Directory to scan, the pyproject.toml is looked up from here. Defaults to the project of the current directory. Repeat it to scan several source roots against one pyproject.toml, looked up from the directory holding all of them
+    #[arg(long, value_name = "DIR")]
+    pub path: Vec<PathBuf>,
+
+    /// The pyproject.toml to read and update, instead of the closest one
+    #[arg(long, value_name = "FILE")]
+    pub pyproject: Option<PathBuf>,
+
+    /// Sync a requirements.txt instead of pyproject.toml, its requirements are the declared dependencies and new ones are appended to it
+    #[arg(long, value_name = "FILE", conflicts_with = "pyproject")]
+    pub requirements: Option<PathBuf>,
+
+    /// Write missing dependencies to pyproject.toml, without this pydepsync only reports them
+    #[arg(long, visible_alias = "write")]
+    pub fix: bool,
+
+    /// Only print the dependencies that would be added, even when `fix = true` is set in the config
+    #[arg(long, conflicts_with = "fix")]
+    pub dry_run: bool,
+
+    /// Like --dry-run, but print the missing dependencies to stderr and exit with 1 when there are any
+    #[arg(long, conflicts_with_all = ["fix", "dry_run"])]
+    pub check: bool,
+
+    /// Exit with an error when a package is declared in several sections with conflicting version specs
+    #[arg(long)]
+    pub fail_on_conflicts: bool,
+
+    /// Exit with an error, before writing anything, when a new dependency couldn't be resolved on any index
+    #[arg(long)]
+    pub fail_on_unresolved: bool,
+
+    /// Don't warn about the new dependencies that couldn't be resolved and are added without a version
+    #[arg(long)]
+    pub quiet_unresolved: bool,
+
+    /// Where the final set of dependencies goes
+    #[arg(long, value_enum, default_value_t = OutputFormat::Pyproject)]
+    pub output_format: OutputFormat,
+
+    /// How the outcome of the run is printed
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+
+    /// File written by --output-format requirements
+    #[arg(long, default_value = "requirements.txt")]
+    pub output: PathBuf,
+
+    /// List of directories to ignore, as globs matching the directory name (`*.egg-info`) or, with a `/`, its path (`**/migrations`). We ignore .venv, .git and target by default
     #[arg(long)]
     pub exclude_dirs: Vec<String>,
 
-    /// List of extra package indexes pydepsync should check when resolving dependencies. We check https://pypi.org/simple by default.
+    /// Files to ignore, as globs matching the file name (`*_pb2.py`) or, with a `/`, its path (`vendor/six.py`)
+    #[arg(long = "exclude-file", value_name = "GLOB")]
+    pub exclude_files: Vec<String>,
+
+    /// Scan a directory that's excluded by default (.venv, .git or target) anyway
+    #[arg(long = "include-dir", value_name = "DIR")]
+    pub include_dirs: Vec<String>,
+
+    /// Don't exclude .venv, .git and target, only the --exclude-dirs
+    #[arg(long)]
+    pub no_default_excludes: bool,
+
+    /// List of extra package indexes pydepsync should check when resolving dependencies. We check https://pypi.org/simple by default. Defaults to the urls in PIP_EXTRA_INDEX_URL
     #[arg(long)]
     pub extra_indexes: Vec<String>,
 
-    /// The index pydepsync should check first when resolving packages
+    /// The index pydepsync should check first when resolving packages. Defaults to PIP_INDEX_URL
     #[arg(long)]
     pub preferred_index: Option<String>,
 
-    /// List of key-value pairs in the format 'key=value'
+    /// List of key-value pairs in the format 'key=value', the value can carry a marker (`win32api=pywin32; sys_platform == 'win32'`) and an empty value (`key=`) drops the import entirely
     #[arg(
         short,
         long,
@@ -25,6 +117,150 @@ pub struct Args {
         action = clap::ArgAction::Append
     )]
     pub remap: Vec<(String, String)>,
+
+    /// TOML file of more remaps, one `import = "package"` per line. Entries given with --remap or in the config win over it, and it wins over the built-in ones
+    #[arg(long, value_name = "FILE")]
+    pub remap_file: Option<PathBuf>,
+
+    /// Only scan python files added since this git ref, imports in files that already existed are ignored
+    #[arg(long, value_name = "GIT_REF")]
+    pub only_new_files: Option<String>,
+
+    /// Only scan python files added or changed since the most recent git tag, to audit what a release introduces
+    #[arg(long, conflicts_with = "only_new_files")]
+    pub since_tag: bool,
+
+    /// Maximum number of requests per second sent to package indexes, across all resolutions
+    #[arg(long, value_name = "RPS", value_parser = rate_limit_parser)]
+    pub rate_limit: Option<f64>,
+
+    /// How many times to ask an index for a package when it fails with a 5xx or a connection error [default: 3]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_attempts: Option<u32>,
+
+    /// Maximum number of packages resolved at once, i.e. connections open to package indexes [default: 8]
+    #[arg(long, value_name = "N", value_parser = max_concurrency_parser)]
+    pub max_concurrency: Option<usize>,
+
+    /// Seconds to wait on a package index, to connect or for a response, before giving up on it [default: 30]
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout_secs: Option<u64>,
+
+    /// How new dependencies are pinned to the version they resolve to [default: compatible]
+    #[arg(long, value_enum)]
+    pub version_strategy: Option<VersionStrategy>,
+
+    /// Resolve to pre-releases and dev releases too, e.g. 2.0.0b1, when they're the newest version
+    #[arg(long)]
+    pub allow_prerelease: bool,
+
+    /// Resolve every package on the indexes, without reading or writing the cache of resolved versions in ~/.cache/pydepsync
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Seconds a cached version stays fresh before it's resolved again [default: 86400]
+    #[arg(long, value_name = "SECS")]
+    pub cache_ttl_secs: Option<u64>,
+
+    /// Don't resolve versions at all, new dependencies are added without one and no index is contacted
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Don't show how many packages have been resolved. It's only shown on a terminal, and never with --format json or --quiet
+    #[arg(long)]
+    pub no_progress: bool,
+
+    /// Extra file extensions to scan besides .py, e.g. `py.j2` for jinja templated python, `pyw` for Windows scripts or `pyi` for type stubs, whose imports only count as needed for type checking
+    #[arg(long)]
+    pub extensions: Vec<String>,
+
+    /// Scan Jupyter notebooks (.ipynb) too, the imports of their code cells minus magics and shell escapes
+    #[arg(long)]
+    pub include_notebooks: bool,
+
+    /// Treat every directory as a local package, not only ones with an __init__.py (PEP 420 namespace packages)
+    #[arg(long)]
+    pub treat_namespace_dirs_as_local: bool,
+
+    /// Abort on the first python file that can't be read or parsed instead of skipping it
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Skip python files bigger than this, like huge generated modules, instead of reading them whole
+    #[arg(long, value_name = "KB")]
+    pub max_file_size_kb: Option<u64>,
+
+    /// Only allow new packages whose license matches one of these case-insensitive regexes
+    #[arg(long, value_name = "PATTERN", value_parser = license_pattern_parser)]
+    pub allow_license: Vec<String>,
+
+    /// Fail when a new package's license matches one of these case-insensitive regexes, e.g. `GPL`
+    #[arg(long, value_name = "PATTERN", value_parser = license_pattern_parser)]
+    pub deny_license: Vec<String>,
+
+    /// Only warn about new packages that break the license policy instead of failing
+    #[arg(long)]
+    pub warn_on_license_violation: bool,
+
+    /// PEM client certificate presented to indexes that require mutual TLS
+    #[arg(long, value_name = "PATH")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key for --client-cert, when it isn't bundled in the certificate file
+    #[arg(long, value_name = "PATH")]
+    pub client_key: Option<PathBuf>,
+
+    /// Proxy to reach the indexes through, e.g. http://proxy.corp:3128. Defaults to HTTPS_PROXY, HTTP_PROXY or ALL_PROXY, and hosts in NO_PROXY are always reached directly
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Pin new dependencies to the versions installed in this site-packages directory, resolving the rest on the index
+    #[arg(long, value_name = "SITE_PACKAGES")]
+    pub from_installed: Option<PathBuf>,
+
+    /// Write each new dependency to the section matching where it's imported, e.g. tests to a test group and docs to a docs extra
+    #[arg(long)]
+    pub write_groups_from_provenance: bool,
+
+    /// With --fix, move group and extra dependencies that runtime code imports to the runtime dependencies instead of warning about them
+    #[arg(long)]
+    pub promote: bool,
+
+    /// Warn about runtime dependencies that nothing imports
+    #[arg(long)]
+    pub find_unused: bool,
+
+    /// Like --find-unused, and with --fix remove them from pyproject.toml
+    #[arg(long)]
+    pub prune: bool,
+
+    /// Keep a comment above the dependencies in pyproject.toml noting when pydepsync last synced them
+    #[arg(long)]
+    pub sync_comment: bool,
+
+    /// Format the whole pyproject.toml with taplo when writing to it. By default only the dependencies added are touched
+    #[arg(long)]
+    pub reformat: bool,
+
+    /// Print the settings after merging the CLI args with the config file, then exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Print the packages the code imports that aren't declared yet, with where they're imported, then exit. No index is asked for a version
+    #[arg(long)]
+    pub list_imports: bool,
+
+    /// Exit with an error when a config file doesn't parse, instead of warning and ignoring it
+    #[arg(long)]
+    pub strict_config: bool,
+
+    /// Log more, -v for debug and -vv for trace. Overrides RUST_LOG
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Log less, -q for warnings and errors only, -qq for errors only and -qqq for nothing. Overrides RUST_LOG
+    #[arg(short, long, action = ArgAction::Count)]
+    pub quiet: u8,
 }
 
 pub fn remap_parser(s: &str) -> Result<(String, String), String> {
@@ -39,3 +275,24 @@ pub fn remap_parser(s: &str) -> Result<(String, String), String> {
         None => Err("Invalid key-value pair format. Use 'key=value'".to_string()),
     }
 }
+
+pub fn rate_limit_parser(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rps) if rps > 0.0 && rps.is_finite() => Ok(rps),
+        _ => Err("Rate limit must be a positive number of requests per second".to_string()),
+    }
+}
+
+pub fn max_concurrency_parser(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err("Max concurrency must be a positive number of packages".to_string()),
+    }
+}
+
+pub fn license_pattern_parser(s: &str) -> Result<String, String> {
+    match regex::Regex::new(s) {
+        Ok(_) => Ok(s.to_string()),
+        Err(e) => Err(format!("Invalid license pattern: {e}")),
+    }
+}
@@ -1,32 +1,471 @@
+use log::warn;
+use regex::Regex;
 use serde::Deserialize;
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+use crate::dependency::{SpecTemplate, SpecTemplateError, Transforms, VERSION_OPERATORS};
+use crate::engine::VersionStrategy;
+use crate::pyproject::SortOrder;
+use crate::sections::{Section, SectionRule};
 
 /// Configuration that can be defined in a .pydepsync.toml
 #[derive(Deserialize, Debug, PartialEq, Default)]
 pub struct Config {
+    pub fix: Option<bool>,
     pub exclude_dirs: Option<Vec<String>>,
+    pub exclude_files: Option<Vec<String>>,
+    pub include_dirs: Option<Vec<String>>,
+    pub no_default_excludes: Option<bool>,
     pub extra_indexes: Option<Vec<String>>,
     pub preferred_index: Option<String>,
     pub remap: Option<HashMap<String, String>>,
+    pub remap_file: Option<PathBuf>,
+    pub rate_limit: Option<f64>,
+    pub max_attempts: Option<u32>,
+    pub max_concurrency: Option<usize>,
+    pub timeout_secs: Option<u64>,
+    pub version_strategy: Option<VersionStrategy>,
+    pub allow_prerelease: Option<bool>,
+    pub no_cache: Option<bool>,
+    pub cache_ttl_secs: Option<u64>,
+    pub offline: Option<bool>,
+    pub no_progress: Option<bool>,
+    pub extensions: Option<Vec<String>>,
+    pub include_notebooks: Option<bool>,
+    pub transforms: Option<Transforms>,
+    pub treat_namespace_dirs_as_local: Option<bool>,
+    pub strict: Option<bool>,
+    pub max_file_size_kb: Option<u64>,
+    pub allow_licenses: Option<Vec<String>>,
+    pub deny_licenses: Option<Vec<String>>,
+    pub warn_on_license_violation: Option<bool>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub proxy: Option<String>,
+    pub from_installed: Option<PathBuf>,
+    pub write_groups_from_provenance: Option<bool>,
+    pub promote: Option<bool>,
+    pub find_unused: Option<bool>,
+    pub prune: Option<bool>,
+    pub section_rules: Option<Vec<SectionRule>>,
+    pub type_checking_section: Option<Section>,
+    pub optional_section: Option<Section>,
+    pub dev_paths: Option<Vec<String>>,
+    pub dev_group: Option<String>,
+    pub spec_template: Option<String>,
+    pub sync_comment: Option<bool>,
+    pub reformat: Option<bool>,
+    pub candidate_ignore_regexes: Option<Vec<String>>,
+    pub sort_order: Option<SortOrder>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to parse the config in {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("failed to read the config in {path:?}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to read the remap file {path:?}: {source}")]
+    RemapFile { path: PathBuf, source: io::Error },
+    #[error("invalid spec_template {template:?}: {source}")]
+    SpecTemplate {
+        template: String,
+        source: SpecTemplateError,
+    },
+    #[error("invalid candidate_ignore_regexes pattern {pattern:?}: {source}")]
+    CandidateIgnoreRegex {
+        pattern: String,
+        source: regex::Error,
+    },
+    #[error("invalid force_operator {0:?}, expected one of {ops}", ops = VERSION_OPERATORS.join(" "))]
+    ForceOperator(String),
+    #[error("invalid rate_limit {0}, expected a positive number of requests per second")]
+    RateLimit(f64),
+    #[error("invalid license pattern {pattern:?}: {source}")]
+    LicensePattern {
+        pattern: String,
+        source: regex::Error,
+    },
+}
+
+impl Config {
+    /// Check the values serde can't, so mistakes surface when the config is loaded
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(rps) = self
+            .rate_limit
+            .filter(|rps| !(*rps > 0.0 && rps.is_finite()))
+        {
+            return Err(ConfigError::RateLimit(rps));
+        }
+        let force_operator = self
+            .transforms
+            .as_ref()
+            .and_then(|t| t.force_operator.as_ref());
+        if let Some(operator) =
+            force_operator.filter(|op| !VERSION_OPERATORS.contains(&op.as_str()))
+        {
+            return Err(ConfigError::ForceOperator(operator.clone()));
+        }
+        if let Some(template) = &self.spec_template {
+            SpecTemplate::parse(template).map_err(|source| ConfigError::SpecTemplate {
+                template: template.clone(),
+                source,
+            })?;
+        }
+        for pattern in self.candidate_ignore_regexes.iter().flatten() {
+            Regex::new(pattern).map_err(|source| ConfigError::CandidateIgnoreRegex {
+                pattern: pattern.clone(),
+                source,
+            })?;
+        }
+        let licenses = self.allow_licenses.iter().chain(&self.deny_licenses);
+        for pattern in licenses.flatten() {
+            Regex::new(pattern).map_err(|source| ConfigError::LicensePattern {
+                pattern: pattern.clone(),
+                source,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the settings this config leaves out from the global one, a
+    /// project's own settings win
+    pub fn merge(self, global: Config) -> Config {
+        Config {
+            fix: self.fix.or(global.fix),
+            exclude_dirs: self.exclude_dirs.or(global.exclude_dirs),
+            exclude_files: self.exclude_files.or(global.exclude_files),
+            include_dirs: self.include_dirs.or(global.include_dirs),
+            no_default_excludes: self.no_default_excludes.or(global.no_default_excludes),
+            extra_indexes: self.extra_indexes.or(global.extra_indexes),
+            preferred_index: self.preferred_index.or(global.preferred_index),
+            // Remaps are merged, the project's wins for an import both remap
+            remap: match (self.remap, global.remap) {
+                (Some(local), Some(mut global)) => {
+                    global.extend(local);
+                    Some(global)
+                }
+                (local, global) => local.or(global),
+            },
+            remap_file: self.remap_file.or(global.remap_file),
+            rate_limit: self.rate_limit.or(global.rate_limit),
+            max_attempts: self.max_attempts.or(global.max_attempts),
+            max_concurrency: self.max_concurrency.or(global.max_concurrency),
+            timeout_secs: self.timeout_secs.or(global.timeout_secs),
+            version_strategy: self.version_strategy.or(global.version_strategy),
+            allow_prerelease: self.allow_prerelease.or(global.allow_prerelease),
+            no_cache: self.no_cache.or(global.no_cache),
+            cache_ttl_secs: self.cache_ttl_secs.or(global.cache_ttl_secs),
+            offline: self.offline.or(global.offline),
+            no_progress: self.no_progress.or(global.no_progress),
+            extensions: self.extensions.or(global.extensions),
+            include_notebooks: self.include_notebooks.or(global.include_notebooks),
+            transforms: self.transforms.or(global.transforms),
+            treat_namespace_dirs_as_local: self
+                .treat_namespace_dirs_as_local
+                .or(global.treat_namespace_dirs_as_local),
+            strict: self.strict.or(global.strict),
+            max_file_size_kb: self.max_file_size_kb.or(global.max_file_size_kb),
+            allow_licenses: self.allow_licenses.or(global.allow_licenses),
+            deny_licenses: self.deny_licenses.or(global.deny_licenses),
+            warn_on_license_violation: self
+                .warn_on_license_violation
+                .or(global.warn_on_license_violation),
+            client_cert: self.client_cert.or(global.client_cert),
+            client_key: self.client_key.or(global.client_key),
+            proxy: self.proxy.or(global.proxy),
+            from_installed: self.from_installed.or(global.from_installed),
+            write_groups_from_provenance: self
+                .write_groups_from_provenance
+                .or(global.write_groups_from_provenance),
+            promote: self.promote.or(global.promote),
+            find_unused: self.find_unused.or(global.find_unused),
+            prune: self.prune.or(global.prune),
+            section_rules: self.section_rules.or(global.section_rules),
+            type_checking_section: self.type_checking_section.or(global.type_checking_section),
+            optional_section: self.optional_section.or(global.optional_section),
+            dev_paths: self.dev_paths.or(global.dev_paths),
+            dev_group: self.dev_group.or(global.dev_group),
+            spec_template: self.spec_template.or(global.spec_template),
+            sync_comment: self.sync_comment.or(global.sync_comment),
+            reformat: self.reformat.or(global.reformat),
+            candidate_ignore_regexes: self
+                .candidate_ignore_regexes
+                .or(global.candidate_ignore_regexes),
+            sort_order: self.sort_order.or(global.sort_order),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/pydepsync/config.toml` (`~/.config` when unset), or
+/// `~/.pydepsync.toml` when that doesn't exist
+pub fn global_config_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home.as_ref()?.join(".config"),
+    };
+    let xdg = config_home.join("pydepsync").join("config.toml");
+    if xdg.is_file() {
+        return Some(xdg);
+    }
+    Some(home?.join(".pydepsync.toml")).filter(|p| p.is_file())
+}
+
+/// Load the config for a project. Each setting comes from the first of its
+/// .pydepsync.toml, the `[tool.pydepsync]` table of `pyproject` and the global
+/// config (see `global_config_path`) that has it. A file that can't be read
+/// or doesn't parse is skipped with a warning, or is an error when `strict`
+pub fn load_config(
+    root: &Path,
+    pyproject: Option<&Path>,
+    strict: bool,
+) -> Result<Config, ConfigError> {
+    load_config_with(root, pyproject, strict, global_config_path().as_deref())
+}
+
+/// Like [`load_config`], with the global config read from `global` rather
+/// than looked up
+pub fn load_config_with(
+    root: &Path,
+    pyproject: Option<&Path>,
+    strict: bool,
+    global: Option<&Path>,
+) -> Result<Config, ConfigError> {
+    let skip_unless_strict = |read: Result<Option<Config>, ConfigError>| match read {
+        Err(e) if !strict => {
+            warn!("{e}, ignoring it");
+            Ok(None)
+        }
+        read => read,
+    };
+    let mut config =
+        skip_unless_strict(read_config(&root.join(".pydepsync.toml")))?.unwrap_or_default();
+    if let Some(pyproject) = pyproject {
+        if let Some(tool) = skip_unless_strict(read_pyproject_config(pyproject))? {
+            config = config.merge(tool);
+        }
+    }
+    if let Some(path) = global {
+        if let Some(global) = skip_unless_strict(read_config(path))? {
+            config = config.merge(global);
+        }
+    }
+    Ok(config)
+}
+
+/// Read the config file at `path`, None when there isn't one. Paths in the
+/// config are relative to the directory it's in
+pub fn read_config(path: &Path) -> Result<Option<Config>, ConfigError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let config = toml::from_str::<Config>(&contents).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(Some(relative_to(config, path)))
+}
+
+#[derive(Deserialize)]
+struct PyProjectTools {
+    tool: Option<Tools>,
 }
 
-/// Load possible config from .pydepsync.toml
-pub fn load_config() -> Config {
-    // Check repo root first, then home directory
-    let path = Path::new(".pydepsync.toml");
-
-    if path.exists() {
-        let contents = match fs::read_to_string(path) {
-            Ok(c) => c,
-            Err(_) => "".to_string(),
-        };
-        return match toml::from_str(&contents) {
-            Ok(config) => config,
-            Err(_) => {
-                eprintln!("Warning: Failed to parse config file at {path:?}");
-                Config::default()
-            }
-        };
-    }
-    Config::default()
+#[derive(Deserialize)]
+struct Tools {
+    pydepsync: Option<toml::Value>,
+}
+
+/// Read the `[tool.pydepsync]` table of a pyproject.toml, None when it has
+/// none. Paths in it are relative to the pyproject.toml
+pub fn read_pyproject_config(path: &Path) -> Result<Option<Config>, ConfigError> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+    // A pyproject.toml that doesn't parse is reported when it's read for its dependencies
+    let Some(table) = toml::from_str::<PyProjectTools>(&contents)
+        .ok()
+        .and_then(|p| p.tool?.pydepsync)
+    else {
+        return Ok(None);
+    };
+    let config = table
+        .try_into::<Config>()
+        .map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(Some(relative_to(config, path)))
+}
+
+/// Read the remaps of a `--remap-file`, a TOML table of import names to the
+/// packages providing them
+pub fn read_remap_file(path: &Path) -> Result<HashMap<String, String>, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|source| ConfigError::RemapFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// Make the paths in a config relative to the directory of the file it was read from
+fn relative_to(mut config: Config, path: &Path) -> Config {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    config.client_cert = config.client_cert.map(|p| dir.join(p));
+    config.client_key = config.client_key.map(|p| dir.join(p));
+    config.from_installed = config.from_installed.map(|p| dir.join(p));
+    config.remap_file = config.remap_file.map(|p| dir.join(p));
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_config_overrides_global() {
+        let home = tempfile::tempdir().unwrap();
+        let global_path = home.path().join(".pydepsync.toml");
+        fs::write(
+            &global_path,
+            "fix = true\nstrict = true\nclient_cert = \"certs/me.pem\"\n\n[remap]\ncv2 = \"opencv-python\"\nyaml = \"PyYAML\"\n",
+        )
+        .unwrap();
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join(".pydepsync.toml"),
+            "fix = false\n\n[remap]\ncv2 = \"opencv-python-headless\"\n",
+        )
+        .unwrap();
+
+        let config = load_config_with(project.path(), None, false, Some(&global_path)).unwrap();
+        assert_eq!(config.fix, Some(false));
+        assert_eq!(config.strict, Some(true));
+        assert_eq!(config.client_cert, Some(home.path().join("certs/me.pem")));
+        assert_eq!(
+            config.remap,
+            Some(HashMap::from([
+                ("cv2".to_string(), "opencv-python-headless".to_string()),
+                ("yaml".to_string(), "PyYAML".to_string()),
+            ]))
+        );
+        assert_eq!(
+            read_config(&project.path().join("missing.toml")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_reads_remap_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("remap.toml");
+        fs::write(
+            &path,
+            "corp_auth = \"corp-auth-client\"\nlegacy = \"\"\n\"corp.metrics\" = \"corp-metrics\"\n",
+        )
+        .unwrap();
+        let remap = read_remap_file(&path).unwrap();
+        assert_eq!(remap.len(), 3);
+        assert_eq!(remap["corp_auth"], "corp-auth-client");
+        assert_eq!(remap["legacy"], "");
+
+        assert!(matches!(
+            read_remap_file(&dir.path().join("missing.toml")),
+            Err(ConfigError::RemapFile { .. })
+        ));
+        fs::write(&path, "corp_auth = 1\n").unwrap();
+        assert!(matches!(
+            read_remap_file(&path),
+            Err(ConfigError::Parse { .. })
+        ));
+
+        fs::write(
+            dir.path().join(".pydepsync.toml"),
+            "remap_file = \"remap.toml\"\n",
+        )
+        .unwrap();
+        let config = read_config(&dir.path().join(".pydepsync.toml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.remap_file, Some(path));
+    }
+
+    #[test]
+    fn test_reads_pyproject_tool_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let pyproject = dir.path().join("pyproject.toml");
+        fs::write(
+            &pyproject,
+            "[project]\nname = \"app\"\n\n[tool.pydepsync]\nexclude_dirs = [\"build\"]\npreferred_index = \"https://nexus.corp/simple\"\n\n[tool.pydepsync.remap]\ncv2 = \"opencv-python\"\n",
+        )
+        .unwrap();
+        let config = read_pyproject_config(&pyproject).unwrap().unwrap();
+        assert_eq!(config.exclude_dirs, Some(vec!["build".to_string()]));
+        assert_eq!(
+            config.remap,
+            Some(HashMap::from([(
+                "cv2".to_string(),
+                "opencv-python".to_string()
+            )]))
+        );
+
+        // The standalone file wins
+        fs::write(
+            dir.path().join(".pydepsync.toml"),
+            "exclude_dirs = [\"dist\"]\n",
+        )
+        .unwrap();
+        let config = load_config_with(dir.path(), Some(&pyproject), false, None).unwrap();
+        assert_eq!(config.exclude_dirs, Some(vec!["dist".to_string()]));
+        assert_eq!(
+            config.preferred_index.as_deref(),
+            Some("https://nexus.corp/simple")
+        );
+
+        fs::write(&pyproject, "[project]\nname = \"app\"\n").unwrap();
+        assert_eq!(read_pyproject_config(&pyproject).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_errors_are_fatal_when_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(".pydepsync.toml"),
+            "exclude_dirs = [\"build\"\nfix = true\n",
+        )
+        .unwrap();
+        let error = load_config_with(dir.path(), None, true, None).unwrap_err();
+        assert!(matches!(error, ConfigError::Parse { .. }));
+        // The toml error points at the line
+        assert!(error.to_string().contains("line 2"), "{error}");
+        assert_eq!(
+            load_config_with(dir.path(), None, false, None)
+                .unwrap()
+                .exclude_dirs,
+            None
+        );
+
+        // There but unreadable, no better than a file that doesn't parse
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".pydepsync.toml")).unwrap();
+        let error = load_config_with(dir.path(), None, true, None).unwrap_err();
+        assert!(matches!(error, ConfigError::Read { .. }));
+        assert!(load_config_with(dir.path(), None, false, None).is_ok());
+    }
 }
@@ -1,62 +1,156 @@
 use regex::Regex;
-use std::collections::HashSet;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashSet};
 use std::fmt::Display;
 use std::hash::Hash;
+use std::sync::LazyLock;
+use thiserror::Error;
 
+use crate::version::Version;
+
+// A version spec, any PEP 440 version including `==4.2.*` wildcards,
+// `+local` labels and `1!` epochs
+const SPEC: &str = r"(?:[~=<>!]={1,2}|[<>]|\^)\s*[\w.\-*+!]+";
+
+static REQUIREMENT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    // The specs can be in parentheses, `requests (>=2.8)`, and nothing can
+    // follow the markers
+    Regex::new(&format!(
+        r"^([A-Za-z0-9\-_.]+)\s*(?:\[(.*?)\])?\s*(?:@\s*(\S+)|\(?\s*({SPEC}(?:\s*,\s*{SPEC})*)\s*\)?)?\s*(?:;\s*(.+?))?\s*$"
+    ))
+    .unwrap()
+});
+
+static SPEC_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([~=<>!]={1,2}|[<>]|\^)\s*([\w.\-*+!]+)").unwrap());
+
+/// The version spec operators of PEP 440
+pub const VERSION_OPERATORS: &[&str] = &["==", "!=", "<=", ">=", "<", ">", "~=", "==="];
+
+/// A PEP 508 requirement: a package name with its extras, version specs
+/// and environment markers, e.g. `requests[socks]>=2.31; python_version >= '3.9'`,
+/// or a direct reference to an archive or repository instead of version specs,
+/// e.g. `mypkg @ git+https://github.com/me/mypkg@v1.0`
 #[derive(Debug, Eq, Clone)]
 pub struct Dependency {
     name: String,
     extras: HashSet<String>,
-    version_spec: Option<(String, String)>, // (specifier, version)
+    version_specs: Vec<(String, String)>, // (specifier, version), comma separated
+    url: Option<String>,
     markers: Option<String>,
 }
 
 impl Display for Dependency {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut dep = String::new();
-        dep += self.name.as_str();
-        if !&self.extras.is_empty() {
-            dep += "[";
-            let mut extras = self
-                .extras
-                .iter()
-                .map(|e| e.to_string())
-                .collect::<Vec<_>>();
-
-            extras.sort(); // Needs to be sorted because iterating over a hashset is not deterministic
-            let extras_str = extras.join(",");
-            dep += extras_str.as_str();
-            dep += "]";
-        }
-        dep = match &self.version_spec {
-            Some(v) => dep + format!("{}{}", v.0, v.1).as_str(),
-            None => dep,
-        };
-        dep = match &self.markers {
-            Some(m) => dep + format!("; {m}").as_str(),
-            None => dep,
-        };
-        write!(f, "{dep}")
+        write!(f, "{}", self.to_dependency_repr())
     }
 }
 
+// Dependencies are equal when their normalized names and extras match,
+// `Flask_SQLAlchemy` is `flask-sqlalchemy` but `celery` isn't `celery[redis]`.
+// Version specs and markers don't count, see `same_package` to ignore extras
 impl PartialEq for Dependency {
     fn eq(&self, other: &Self) -> bool {
-        self.name.to_lowercase() == other.name.to_lowercase()
+        self.normalized_name() == other.normalized_name()
+            && self.normalized_extras() == other.normalized_extras()
     }
 }
 
 impl Hash for Dependency {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.name.to_lowercase().hash(state);
+        self.normalized_name().hash(state);
+        self.normalized_extras().hash(state);
     }
 }
 
+/// Normalize a distribution name the way PEP 503 does: lowercase, with every
+/// run of `-`, `_` and `.` collapsed to a single `-`
+pub fn normalize_name(name: &str) -> String {
+    name.split(['-', '_', '.'])
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
 impl Dependency {
     pub fn name(&self) -> String {
         self.name.clone()
     }
 
+    /// The name as package indexes know it, see [`normalize_name`]
+    pub fn normalized_name(&self) -> String {
+        normalize_name(&self.name)
+    }
+
+    /// Whether both are the same package, whatever their extras
+    pub fn same_package(&self, other: &Dependency) -> bool {
+        self.normalized_name() == other.normalized_name()
+    }
+
+    /// Whether declaring `other` also gives us this dependency, it's the same
+    /// package with at least the same extras
+    pub fn satisfied_by(&self, other: &Dependency) -> bool {
+        self.same_package(other)
+            && self
+                .normalized_extras()
+                .is_subset(&other.normalized_extras())
+    }
+
+    // Extras are normalized like names (PEP 685)
+    fn normalized_extras(&self) -> BTreeSet<String> {
+        self.extras.iter().map(|e| normalize_name(e)).collect()
+    }
+
+    pub fn version_specs(&self) -> &[(String, String)] {
+        &self.version_specs
+    }
+
+    pub fn extras(&self) -> &HashSet<String> {
+        &self.extras
+    }
+
+    /// The url of a direct reference, `name @ url`
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    pub fn markers(&self) -> Option<&str> {
+        self.markers.as_deref()
+    }
+
+    /// The same dependency, extras and markers included, with a single new version spec
+    pub fn with_version_spec(&self, specifier: &str, version: &str) -> Dependency {
+        Dependency {
+            version_specs: vec![(specifier.to_string(), version.to_string())],
+            ..self.clone()
+        }
+    }
+
+    /// The same dependency with the extras of `other` added to its own
+    pub fn with_extras_of(&self, other: &Dependency) -> Dependency {
+        Dependency {
+            extras: self.extras.union(&other.extras).cloned().collect(),
+            ..self.clone()
+        }
+    }
+
+    /// The same dependency with environment markers, e.g. `python_version < '3.8'`
+    pub fn with_markers(&self, markers: &str) -> Dependency {
+        Dependency {
+            markers: Some(markers.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// The same dependency without any version spec
+    pub fn without_version_specs(&self) -> Dependency {
+        Dependency {
+            version_specs: Vec::new(),
+            ..self.clone()
+        }
+    }
+
     pub fn to_dependency_repr(&self) -> String {
         let mut dep = String::new();
         dep += self.name.as_str();
@@ -73,20 +167,24 @@ impl Dependency {
             dep += extras_str.as_str();
             dep += "]";
         }
-        dep = match &self.version_spec {
-            Some(v) => dep + format!("{}{}", v.0, v.1).as_str(),
-            None => dep,
-        };
-        dep = match &self.markers {
-            Some(m) => dep + format!("; {m}").as_str(),
-            None => dep,
+        dep += &self
+            .version_specs
+            .iter()
+            .map(|(spec, version)| format!("{spec}{version}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        dep = match (&self.url, &self.markers) {
+            // Markers need a space after the url or the `;` would be part of it
+            (Some(url), Some(m)) => dep + format!(" @ {url} ; {m}").as_str(),
+            (Some(url), None) => dep + format!(" @ {url}").as_str(),
+            (None, Some(m)) => dep + format!("; {m}").as_str(),
+            (None, None) => dep,
         };
         dep
     }
-    pub fn parse(input: &str) -> Option<Self> {
-        let re = Regex::new(r#"^([A-Za-z0-9\-_.]+)(?:\[(.*?)\])?(?:\s*([~=<>!]={1,2}|[<>]|\^)\s*([\d\w\-.]+))?\s*(?:;\s*(.+))?"#).unwrap();
 
-        let caps = re.captures(input)?;
+    pub fn parse(input: &str) -> Option<Self> {
+        let caps = REQUIREMENT_RE.captures(input)?;
 
         let name = caps.get(1)?.as_str().to_string();
 
@@ -100,22 +198,162 @@ impl Dependency {
             })
             .unwrap_or_default();
 
-        let version_spec = match (caps.get(3), caps.get(4)) {
-            (Some(spec), Some(ver)) => Some((spec.as_str().to_string(), ver.as_str().to_string())),
-            _ => None,
-        };
+        let url = caps.get(3).map(|m| m.as_str().to_string());
+
+        // Every constraint of a range like `>=3.2,<4.0`
+        let version_specs = caps
+            .get(4)
+            .map(|m| {
+                SPEC_RE
+                    .captures_iter(m.as_str())
+                    .map(|c| (c[1].to_string(), c[2].to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
 
         let markers = caps.get(5).map(|m| m.as_str().to_string());
 
         Some(Dependency {
             name,
             extras,
-            version_spec,
+            version_specs,
+            url,
             markers,
         })
     }
 }
 
+/// Built-in transformations applied to each new dependency right before it's written
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Transforms {
+    /// Drop environment markers, e.g. `; python_version < '3.9'`
+    pub strip_markers: bool,
+    /// Write package names in lowercase
+    pub lowercase_names: bool,
+    /// Replace the operator of the version spec, e.g. `==` to pin exactly
+    pub force_operator: Option<String>,
+}
+
+impl Transforms {
+    pub fn apply(&self, dep: &Dependency) -> Dependency {
+        let mut dep = dep.clone();
+        if self.strip_markers {
+            dep.markers = None;
+        }
+        if self.lowercase_names {
+            dep.name = dep.name.to_lowercase();
+        }
+        if let Some(operator) = &self.force_operator {
+            for (spec, _) in &mut dep.version_specs {
+                *spec = operator.clone();
+            }
+        }
+        dep
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+    Name,
+    Version,
+    Major,
+    Minor,
+    Patch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SpecTemplateError {
+    #[error("unknown placeholder {{{0}}}, expected one of name, version, major, minor, patch")]
+    UnknownPlaceholder(String),
+    #[error("unbalanced braces")]
+    Unbalanced,
+    #[error("the {{name}} placeholder is required")]
+    MissingName,
+}
+
+/// A house style for writing resolved dependencies, e.g. `{name} >= {version}`
+/// or `{name}=={major}.{minor}.*`. `{name}` includes any extras, `{major}`,
+/// `{minor}` and `{patch}` are the release segments of the resolved version
+/// (0 when missing) and environment markers are appended after rendering
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl SpecTemplate {
+    pub fn parse(template: &str) -> Result<Self, SpecTemplateError> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(SpecTemplateError::Unbalanced);
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or(SpecTemplateError::Unbalanced)?
+                + start;
+            let placeholder = match &rest[start + 1..end] {
+                "name" => Placeholder::Name,
+                "version" => Placeholder::Version,
+                "major" => Placeholder::Major,
+                "minor" => Placeholder::Minor,
+                "patch" => Placeholder::Patch,
+                other if other.contains('{') => return Err(SpecTemplateError::Unbalanced),
+                other => return Err(SpecTemplateError::UnknownPlaceholder(other.to_string())),
+            };
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            parts.push(TemplatePart::Placeholder(placeholder));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+        if !parts.contains(&TemplatePart::Placeholder(Placeholder::Name)) {
+            return Err(SpecTemplateError::MissingName);
+        }
+        Ok(SpecTemplate { parts })
+    }
+
+    /// Render `dep` with this template, `None` when it has no version to fill in
+    pub fn render(&self, dep: &Dependency) -> Option<String> {
+        let (_, version) = dep.version_specs.first()?;
+        let release = Version::parse(version)?.release().to_vec();
+        let segment = |i: usize| release.get(i).copied().unwrap_or(0).to_string();
+        let name = Dependency {
+            version_specs: Vec::new(),
+            url: None,
+            markers: None,
+            ..dep.clone()
+        }
+        .to_dependency_repr();
+
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(text) => rendered += text,
+                TemplatePart::Placeholder(Placeholder::Name) => rendered += &name,
+                TemplatePart::Placeholder(Placeholder::Version) => rendered += version,
+                TemplatePart::Placeholder(Placeholder::Major) => rendered += &segment(0),
+                TemplatePart::Placeholder(Placeholder::Minor) => rendered += &segment(1),
+                TemplatePart::Placeholder(Placeholder::Patch) => rendered += &segment(2),
+            }
+        }
+        if let Some(markers) = &dep.markers {
+            rendered += &format!("; {markers}");
+        }
+        Some(rendered)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,7 +370,47 @@ mod tests {
         let candidate = "Django~=3.2";
         let res = Dependency::parse(candidate).unwrap();
         assert_eq!(res.name, "Django");
-        assert_eq!(res.version_spec, Some(("~=".into(), "3.2".into())));
+        assert_eq!(res.version_specs, vec![("~=".into(), "3.2".into())]);
+    }
+
+    #[test]
+    fn test_keeps_every_constraint_of_a_range() {
+        let res = Dependency::parse("Django>=3.2, <4.0; python_version >= '3.8'").unwrap();
+        assert_eq!(
+            res.version_specs,
+            vec![(">=".into(), "3.2".into()), ("<".into(), "4.0".into())]
+        );
+        assert_eq!(res.markers.as_deref(), Some("python_version >= '3.8'"));
+        assert_eq!(
+            res.to_dependency_repr(),
+            "Django>=3.2,<4.0; python_version >= '3.8'"
+        );
+    }
+
+    #[test]
+    fn test_keeps_full_pep_440_versions() {
+        for requirement in [
+            "Django==4.2.*",
+            "torch==2.1.0+cpu",
+            "pytz==2023!1.0",
+            "numpy>=1.26.0rc1,!=1.26.1.post1",
+            "legacy===1.0-custom",
+        ] {
+            let dep = Dependency::parse(requirement).unwrap();
+            assert_eq!(dep.to_dependency_repr(), requirement);
+        }
+        assert_eq!(
+            Dependency::parse("requests (>=2.8.1, <3) ; python_version >= '3.8'")
+                .unwrap()
+                .to_dependency_repr(),
+            "requests>=2.8.1,<3; python_version >= '3.8'"
+        );
+    }
+
+    #[test]
+    fn test_rejects_text_after_the_spec() {
+        assert_eq!(Dependency::parse("Django==4.2 please"), None);
+        assert_eq!(Dependency::parse("Django==4.2?"), None);
     }
 
     #[test]
@@ -159,7 +437,7 @@ mod tests {
         assert_eq!(res.name, "pandas");
         assert!(res.extras.contains("excel"));
         assert!(res.extras.contains("postgres"));
-        assert_eq!(res.version_spec, Some((">=".into(), "1.3.0".into())));
+        assert_eq!(res.version_specs, vec![(">=".into(), "1.3.0".into())]);
         assert_eq!(res.markers, Some("platform_system != 'Windows'".into()));
     }
 
@@ -169,4 +447,148 @@ mod tests {
         let dep = Dependency::parse(candidate).unwrap();
         assert_eq!(format!("{dep}"), candidate);
     }
+
+    #[test]
+    fn test_can_parse_direct_references() {
+        let dep =
+            Dependency::parse("mypkg @ https://example.com/mypkg-1.0-py3-none-any.whl").unwrap();
+        assert_eq!(dep.name, "mypkg");
+        assert_eq!(
+            dep.url(),
+            Some("https://example.com/mypkg-1.0-py3-none-any.whl")
+        );
+        assert!(dep.version_specs.is_empty());
+
+        for requirement in [
+            "mypkg[cli] @ git+https://github.com/me/mypkg@v1.0",
+            "pywin32 @ file:///wheels/pywin32-306-cp312-win_amd64.whl ; sys_platform == 'win32'",
+        ] {
+            let dep = Dependency::parse(requirement).unwrap();
+            assert_eq!(dep.to_dependency_repr(), requirement);
+        }
+        let dep = Dependency::parse("mypkg@git+ssh://git@github.com/me/mypkg.git").unwrap();
+        assert_eq!(dep.url(), Some("git+ssh://git@github.com/me/mypkg.git"));
+        assert_eq!(
+            dep.to_dependency_repr(),
+            "mypkg @ git+ssh://git@github.com/me/mypkg.git"
+        );
+    }
+
+    #[test]
+    fn test_extras_are_part_of_equality() {
+        let celery = Dependency::parse("celery>=5").unwrap();
+        let with_redis = Dependency::parse("Celery[redis]").unwrap();
+        assert_ne!(celery, with_redis);
+        assert_eq!(with_redis, Dependency::parse("celery[Redis]~=5.4").unwrap());
+        assert_eq!(HashSet::from([celery.clone(), with_redis.clone()]).len(), 2);
+
+        assert!(celery.same_package(&with_redis));
+        assert!(celery.satisfied_by(&with_redis));
+        assert!(!with_redis.satisfied_by(&celery));
+    }
+
+    #[test]
+    fn test_normalized_name() {
+        let dep = Dependency::parse("Flask_SQLAlchemy>=3.0").unwrap();
+        assert_eq!(dep.normalized_name(), "flask-sqlalchemy");
+        assert_eq!(dep.name(), "Flask_SQLAlchemy");
+        assert_eq!(normalize_name("zope.interface"), "zope-interface");
+        assert_eq!(normalize_name("Some__Weird-._Name"), "some-weird-name");
+
+        let declared = HashSet::from([Dependency::parse("flask-sqlalchemy").unwrap()]);
+        assert!(declared.contains(&dep));
+        assert_ne!(dep, Dependency::parse("flasksqlalchemy").unwrap());
+    }
+
+    #[test]
+    fn test_transform_strip_markers() {
+        let dep = Dependency::parse("pywin32>=306; sys_platform == 'win32'").unwrap();
+        let transforms = Transforms {
+            strip_markers: true,
+            ..Default::default()
+        };
+        assert_eq!(transforms.apply(&dep).to_dependency_repr(), "pywin32>=306");
+    }
+
+    #[test]
+    fn test_transform_lowercase_names() {
+        let dep = Dependency::parse("Django[Argon2]~=5.1").unwrap();
+        let transforms = Transforms {
+            lowercase_names: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            transforms.apply(&dep).to_dependency_repr(),
+            "django[Argon2]~=5.1"
+        );
+    }
+
+    #[test]
+    fn test_transform_force_operator() {
+        let transforms = Transforms {
+            force_operator: Some("==".to_string()),
+            ..Default::default()
+        };
+        let dep = Dependency::parse("requests~=2.32.3").unwrap();
+        assert_eq!(
+            transforms.apply(&dep).to_dependency_repr(),
+            "requests==2.32.3"
+        );
+        // Nothing to force without a version
+        let dep = Dependency::parse("requests").unwrap();
+        assert_eq!(transforms.apply(&dep).to_dependency_repr(), "requests");
+    }
+
+    #[test]
+    fn test_default_transforms_are_a_noop() {
+        let candidate = "pandas[excel,postgres]>=1.3.0; platform_system != 'Windows'";
+        let dep = Dependency::parse(candidate).unwrap();
+        assert_eq!(
+            Transforms::default().apply(&dep).to_dependency_repr(),
+            candidate
+        );
+    }
+
+    #[test]
+    fn test_spec_template() {
+        let dep = Dependency::parse("Django[argon2]~=4.2.7; python_version >= '3.10'").unwrap();
+        let template = SpecTemplate::parse("{name} >= {version}").unwrap();
+        assert_eq!(
+            template.render(&dep).unwrap(),
+            "Django[argon2] >= 4.2.7; python_version >= '3.10'"
+        );
+
+        let template = SpecTemplate::parse("{name}=={major}.{minor}.*").unwrap();
+        let dep = Dependency::parse("requests~=2.32.3").unwrap();
+        assert_eq!(template.render(&dep).unwrap(), "requests==2.32.*");
+        let dep = Dependency::parse("attrs~=24").unwrap();
+        assert_eq!(template.render(&dep).unwrap(), "attrs==24.0.*");
+
+        // Nothing to fill the version placeholders with
+        assert_eq!(template.render(&Dependency::parse("attrs").unwrap()), None);
+    }
+
+    #[test]
+    fn test_spec_template_validation() {
+        assert_eq!(
+            SpecTemplate::parse("{name}>={build}"),
+            Err(SpecTemplateError::UnknownPlaceholder("build".to_string()))
+        );
+        assert_eq!(
+            SpecTemplate::parse("{name>={version}"),
+            Err(SpecTemplateError::Unbalanced)
+        );
+        assert_eq!(
+            SpecTemplate::parse("{name}>={version"),
+            Err(SpecTemplateError::Unbalanced)
+        );
+        assert_eq!(
+            SpecTemplate::parse("{name}}"),
+            Err(SpecTemplateError::Unbalanced)
+        );
+        assert_eq!(
+            SpecTemplate::parse("pkg>={version}"),
+            Err(SpecTemplateError::MissingName)
+        );
+    }
 }
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+/// How long a resolved version is trusted when the options don't say
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// `$XDG_CACHE_HOME/pydepsync/index.json`, or `~/.cache/pydepsync/index.json`
+pub fn default_cache_path() -> Option<PathBuf> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_home.join("pydepsync").join("index.json"))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Entry {
+    version: String,
+    /// Unix time the version was resolved at
+    resolved_at: u64,
+    /// What the version was picked under, a different setting is a miss
+    #[serde(default)]
+    settings: String,
+}
+
+/// Latest versions resolved on each index, kept on disk between runs and
+/// keyed by index url and normalized package name
+#[derive(Debug)]
+pub struct IndexCache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IndexCache {
+    /// Load the cache at `path`, starting empty when it doesn't exist yet or
+    /// can't be read
+    pub fn open(path: &Path, ttl: Duration) -> Self {
+        let entries = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring the unreadable cache at {path:?}");
+                debug!("Error {e}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        IndexCache {
+            path: path.to_path_buf(),
+            ttl,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// The version cached for a package on an index, unless it expired or
+    /// was resolved with different settings
+    pub fn get(&self, index: &str, name: &str, settings: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key(index, name))?;
+        let age = now().saturating_sub(entry.resolved_at);
+        (age < self.ttl.as_secs() && entry.settings == settings).then(|| entry.version.clone())
+    }
+
+    pub fn insert(&self, index: &str, name: &str, settings: &str, version: &str) {
+        self.entries.lock().unwrap().insert(
+            key(index, name),
+            Entry {
+                version: version.to_string(),
+                resolved_at: now(),
+                settings: settings.to_string(),
+            },
+        );
+    }
+
+    /// Write the cache back to disk, dropping expired entries
+    pub fn save(&self) -> Result<(), io::Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = now();
+        entries.retain(|_, e| now.saturating_sub(e.resolved_at) < self.ttl.as_secs());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_string(&*entries)?)
+    }
+}
+
+fn key(index: &str, name: &str) -> String {
+    format!("{} {name}", index.trim_end_matches('/'))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_through_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("index.json");
+        let cache = IndexCache::open(&path, DEFAULT_TTL);
+        assert_eq!(cache.get("https://pypi.org/simple", "django", ""), None);
+        cache.insert("https://pypi.org/simple/", "django", "", "5.1.7");
+        cache.save().unwrap();
+
+        let cache = IndexCache::open(&path, DEFAULT_TTL);
+        assert_eq!(
+            cache.get("https://pypi.org/simple", "django", ""),
+            Some("5.1.7".to_string())
+        );
+        assert_eq!(
+            cache.get("https://test.pypi.org/simple", "django", ""),
+            None
+        );
+        assert_eq!(cache.get("https://pypi.org/simple", "django", "pre"), None);
+    }
+
+    #[test]
+    fn test_expired_entries_are_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let cache = IndexCache::open(&path, Duration::ZERO);
+        cache.insert("https://pypi.org/simple", "django", "", "5.1.7");
+        assert_eq!(cache.get("https://pypi.org/simple", "django", ""), None);
+        cache.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_unreadable_cache_starts_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        fs::write(&path, "not json").unwrap();
+        let cache = IndexCache::open(&path, DEFAULT_TTL);
+        assert_eq!(cache.get("https://pypi.org/simple", "django", ""), None);
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::dependency::Dependency;
+use regex::Regex;
+
+use crate::dependency::{normalize_name, Dependency};
 
 use super::{irregulars, stdlib};
 
@@ -8,17 +10,62 @@ use super::{irregulars, stdlib};
 pub struct DependencyEvaluator<'a> {
     stdlib_pakages: HashSet<&'a str>,
     irregulars_to_remap: HashMap<String, String>,
+    /// The imports remapped to each package, keyed by normalized name
+    imports_by_package: HashMap<String, Vec<String>>,
+    ignore_patterns: Vec<Regex>,
 }
 
 impl DependencyEvaluator<'_> {
     pub fn new(extras_to_remap: HashMap<String, String>) -> Self {
+        // The remaps given win over the built-in ones for the same import
         let mut irregulars = extras_to_remap;
         for (key, val) in irregulars::get_python_irregulars() {
-            irregulars.insert(key.to_string(), val.to_string());
+            irregulars
+                .entry(key.to_string())
+                .or_insert_with(|| val.to_string());
+        }
+        let mut imports_by_package: HashMap<String, Vec<String>> = HashMap::new();
+        for (import, package) in &irregulars {
+            if !package.is_empty() {
+                imports_by_package
+                    .entry(normalize_name(package_name(package)))
+                    .or_default()
+                    .push(import.clone());
+            }
+        }
+        for imports in imports_by_package.values_mut() {
+            imports.sort();
         }
         DependencyEvaluator {
             stdlib_pakages: stdlib::get_python_stdlib_modules(),
             irregulars_to_remap: irregulars,
+            imports_by_package,
+            ignore_patterns: Vec::new(),
+        }
+    }
+
+    /// Drop packages whose names match any of `patterns`, checked after remapping
+    pub fn ignore_patterns(mut self, patterns: Vec<Regex>) -> Self {
+        self.ignore_patterns = patterns;
+        self
+    }
+
+    /// The package providing an import, after remapping, empty when the
+    /// import is remapped to nothing
+    pub fn package_for<'c>(&'c self, candidate: &'c str) -> &'c str {
+        match self.irregulars_to_remap.get(candidate) {
+            Some(m) => m.as_str(),
+            None => candidate,
+        }
+    }
+
+    /// The names a package is expected to be imported as, the imports
+    /// remapped to it, or its own name as a module when there are none
+    pub fn import_names_for(&self, package: &str) -> Vec<String> {
+        let name = package_name(package);
+        match self.imports_by_package.get(&normalize_name(name)) {
+            Some(imports) => imports.clone(),
+            None => vec![name.to_lowercase().replace('-', "_")],
         }
     }
 
@@ -32,28 +79,46 @@ impl DependencyEvaluator<'_> {
         // And convert anything that matches one of the "irregulars"
         // i.e python packages that are called something but to import code
         // from that package is called something else
-        let deps: HashSet<String> = candidates
-            .iter()
+        // Remapped names are compared as dependencies, not strings, so they get the
+        // same name normalization as the existing deps and each other
+        // Sorted so the name a merged dependency keeps doesn't depend on hashing
+        let mut candidates: Vec<&String> = candidates.iter().collect();
+        candidates.sort();
+        let mut by_name: HashMap<String, Dependency> = HashMap::new();
+        for dep in candidates
+            .into_iter()
             .filter(|c| !self.stdlib_pakages.contains(&c.as_str()))
-            .filter(|&c| !local_packages.clone().contains(c))
-            .cloned()
-            .map(|c| {
-                let hit = self.irregulars_to_remap.get(c.as_str());
-                match hit {
-                    Some(m) => m.to_string(),
-                    None => c,
-                }
-            })
-            // filter on existing needs to come last
-            .filter(|c| !existing_deps.contains(&Dependency::parse(c).unwrap()))
-            .collect();
-
-        deps.iter()
-            .map(|d| Dependency::parse(d.as_str()).unwrap())
+            .filter(|c| !local_packages.contains(*c))
+            .map(|c| self.package_for(c))
+            // Remapping to an empty name means the import never needs a dependency
+            .filter(|c| !c.is_empty())
+            // Remaps can carry markers, e.g. `pywin32; sys_platform == 'win32'`
+            .map(|c| Dependency::parse(c).unwrap())
+            .filter(|d| !self.ignore_patterns.iter().any(|p| p.is_match(&d.name())))
+        {
+            // Imports needing different extras of a package need all of them
+            by_name
+                .entry(dep.normalized_name())
+                .and_modify(|d| *d = d.with_extras_of(&dep))
+                .or_insert(dep);
+        }
+        by_name
+            .into_values()
+            // filter on existing needs to come last, `celery` is declared by
+            // `celery[redis]` but `celery[redis]` isn't by `celery`
+            .filter(|d| !existing_deps.iter().any(|e| d.satisfied_by(e)))
             .collect()
     }
 }
 
+// The name of a remapped package, without the extras or markers it can carry
+fn package_name(package: &str) -> &str {
+    let end = package
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(package.len());
+    &package[..end]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,6 +164,31 @@ mod tests {
         assert!(res.contains(&Dependency::parse("pyAFQ").unwrap()));
     }
 
+    #[test]
+    fn test_extra_irregulars_override_built_in_ones() {
+        let evaluator = DependencyEvaluator::new(HashMap::from([(
+            "PIL".to_string(),
+            "pillow-simd".to_string(),
+        )]));
+        assert_eq!(evaluator.package_for("PIL"), "pillow-simd");
+        assert_eq!(evaluator.package_for("AFQ"), "pyAFQ");
+    }
+
+    #[test]
+    fn test_import_names_for_packages() {
+        let evaluator = DependencyEvaluator::new(HashMap::from([(
+            "win32api".to_string(),
+            "pywin32; sys_platform == 'win32'".to_string(),
+        )]));
+        assert_eq!(evaluator.import_names_for("scikit-learn"), ["sklearn"]);
+        assert_eq!(evaluator.import_names_for("pyyaml"), ["yaml"]);
+        assert_eq!(evaluator.import_names_for("PyWin32>=306"), ["win32api"]);
+        assert_eq!(
+            evaluator.import_names_for("typing-extensions"),
+            ["typing_extensions"]
+        );
+    }
+
     #[test]
     fn test_remaps_extra_irregulars() {
         let evaluator = DependencyEvaluator::new(HashMap::from([(
@@ -110,4 +200,107 @@ mod tests {
         assert_eq!(res.len(), 1);
         assert!(res.contains(&Dependency::parse("ThingToRemap").unwrap()));
     }
+
+    #[test]
+    fn test_remapped_irregular_dedups_against_existing_casing() {
+        let evaluator = DependencyEvaluator::new(HashMap::new());
+        // PIL remaps to Pillow, which is already declared as pillow
+        let candidates = HashSet::from(["PIL".to_string(), "requests".to_string()]);
+        let res = evaluator.evaluate(
+            candidates,
+            HashSet::from([Dependency::parse("pillow>=10").unwrap()]),
+            HashSet::new(),
+        );
+        assert_eq!(res, HashSet::from([Dependency::parse("requests").unwrap()]));
+    }
+
+    #[test]
+    fn test_remapped_names_dedup_across_casings() {
+        let evaluator = DependencyEvaluator::new(HashMap::from([(
+            "yaml_compat".to_string(),
+            "pyyaml".to_string(),
+        )]));
+        // yaml is a built in irregular for PyYAML
+        let candidates = HashSet::from(["yaml".to_string(), "yaml_compat".to_string()]);
+        let res = evaluator.evaluate(candidates, HashSet::new(), HashSet::new());
+        assert_eq!(res.len(), 1);
+        assert!(res.contains(&Dependency::parse("PyYAML").unwrap()));
+    }
+
+    #[test]
+    fn test_remap_to_empty_drops_candidate() {
+        let evaluator =
+            DependencyEvaluator::new(HashMap::from([("gi".to_string(), String::new())]));
+        let candidates = HashSet::from(["gi".to_string(), "requests".to_string()]);
+        let res = evaluator.evaluate(candidates, HashSet::new(), HashSet::new());
+        assert_eq!(res, HashSet::from([Dependency::parse("requests").unwrap()]));
+    }
+
+    #[test]
+    fn test_remap_with_marker() {
+        let evaluator = DependencyEvaluator::new(HashMap::from([(
+            "win32api".to_string(),
+            "pywin32; sys_platform == 'win32'".to_string(),
+        )]));
+        let candidates = HashSet::from(["win32api".to_string()]);
+        let res = evaluator.evaluate(candidates, HashSet::new(), HashSet::new());
+        let dep = res.into_iter().next().unwrap();
+        assert_eq!(dep.name(), "pywin32");
+        assert_eq!(dep.markers(), Some("sys_platform == 'win32'"));
+    }
+
+    #[test]
+    fn test_ignore_patterns_apply_after_remapping() {
+        let evaluator = DependencyEvaluator::new(HashMap::from([(
+            "billing".to_string(),
+            "corp_billing".to_string(),
+        )]))
+        .ignore_patterns(vec![Regex::new("^corp_").unwrap()]);
+        let candidates = HashSet::from([
+            "corp_auth".to_string(),
+            "billing".to_string(),
+            "django".to_string(),
+        ]);
+        let res = evaluator.evaluate(candidates, HashSet::new(), HashSet::new());
+        assert_eq!(res, HashSet::from([Dependency::parse("django").unwrap()]));
+    }
+
+    #[test]
+    fn test_declared_extras_cover_plain_imports() {
+        let evaluator = DependencyEvaluator::new(HashMap::from([(
+            "redis_tasks".to_string(),
+            "celery[redis]".to_string(),
+        )]));
+        let candidates = HashSet::from(["celery".to_string()]);
+        let declared = HashSet::from([Dependency::parse("celery[redis]~=5.4").unwrap()]);
+        assert!(evaluator
+            .evaluate(candidates, declared, HashSet::new())
+            .is_empty());
+
+        // An import that needs an extra isn't covered by the plain package
+        let candidates = HashSet::from(["redis_tasks".to_string()]);
+        let declared = HashSet::from([Dependency::parse("celery~=5.4").unwrap()]);
+        let res = evaluator.evaluate(candidates, declared, HashSet::new());
+        assert_eq!(
+            res,
+            HashSet::from([Dependency::parse("celery[redis]").unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_merges_extras_of_the_same_package() {
+        let evaluator = DependencyEvaluator::new(HashMap::from([
+            ("redis_tasks".to_string(), "celery[redis]".to_string()),
+            ("sqs_tasks".to_string(), "Celery[sqs]".to_string()),
+        ]));
+        let candidates = HashSet::from([
+            "celery".to_string(),
+            "redis_tasks".to_string(),
+            "sqs_tasks".to_string(),
+        ]);
+        let res = evaluator.evaluate(candidates, HashSet::new(), HashSet::new());
+        assert_eq!(res.len(), 1);
+        let celery = res.into_iter().next().unwrap();
+        assert_eq!(celery, Dependency::parse("celery[redis,sqs]").unwrap());
+    }
 }
@@ -1,45 +1,98 @@
-use std::{fs, io, path::PathBuf};
-use walkdir::WalkDir;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use globset::{GlobBuilder, GlobMatcher};
+use log::warn;
+use walkdir::{DirEntry, WalkDir};
 
 pub struct PythonFileFinder {
-    excluded_dirs: Vec<String>,
+    excluded_dirs: Vec<ExcludedPath>,
+    excluded_files: Vec<ExcludedPath>,
+    extensions: Vec<String>,
+    namespace_packages: bool,
 }
 
 impl PythonFileFinder {
     pub fn new() -> Self {
         Self {
-            excluded_dirs: vec!["venv".to_string(), ".git".to_string()],
+            excluded_dirs: vec![ExcludedPath::new("venv"), ExcludedPath::new(".git")],
+            excluded_files: Vec::new(),
+            extensions: vec!["py".to_string()],
+            namespace_packages: false,
         }
     }
 
-    /// Add directories to exclude from the search
+    /// Count any directory as a local package, not just the ones with an
+    /// __init__.py, for projects using implicit namespace packages (PEP 420)
+    pub fn namespace_packages(mut self, namespace_packages: bool) -> Self {
+        self.namespace_packages = namespace_packages;
+        self
+    }
+
+    /// Add file extensions to search for, these can span multiple dots like `py.j2`
+    pub fn extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions.extend(extensions);
+        self
+    }
+
+    /// Add directories to exclude from the search, as glob patterns. Patterns
+    /// without a `/` match directory names, like `build-*` or `*.egg-info`,
+    /// others the path from where the search starts, like `**/migrations`
     pub fn exclude_dirs(mut self, dirs: Vec<String>) -> Self {
-        self.excluded_dirs.extend(dirs);
+        self.excluded_dirs
+            .extend(dirs.iter().map(|d| ExcludedPath::new(d)));
         self
     }
 
+    /// Add files to exclude from the search, as glob patterns matched like
+    /// the directories, `*_pb2.py` against names and `vendor/six.py` against
+    /// the path from where the search starts
+    pub fn exclude_files(mut self, files: Vec<String>) -> Self {
+        self.excluded_files
+            .extend(files.iter().map(|f| ExcludedPath::new(f)));
+        self
+    }
+
+    fn is_excluded(&self, start_path: &Path, entry: &DirEntry) -> bool {
+        if !entry.file_type().is_dir() {
+            return false;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(start_path)
+            .unwrap_or(entry.path());
+        self.excluded_dirs
+            .iter()
+            .any(|excluded| excluded.is_match(relative))
+    }
+
     /// Similar to find files but this returns dir names too
     /// Because imports can reference just a dir if code is in the
-    /// __init__.py file
+    /// __init__.py file. Only directories with an __init__.py are packages
+    /// unless namespace packages are enabled
     pub fn find_local_packages(&self, start_path: &PathBuf) -> Result<Vec<PathBuf>, io::Error> {
         let root_package = fs::canonicalize(PathBuf::from(start_path)).unwrap();
         let mut local_packages = vec![root_package];
         for entry in WalkDir::new(start_path)
+            .min_depth(1)
             .follow_links(true)
             .into_iter()
-            .filter_entry(|e| {
-                // Skip excluded directories
-                if e.file_type().is_dir() {
-                    if let Some(dir_name) = e.file_name().to_str() {
-                        return !self.excluded_dirs.contains(&dir_name.to_string());
-                    }
-                }
-                true
-            })
+            .filter_entry(|e| !self.is_excluded(start_path, e))
             .filter_map(|e| e.ok())
         {
             let path = entry.path();
-            local_packages.push(path.to_path_buf());
+            let is_package = if entry.file_type().is_dir() {
+                self.namespace_packages || path.join("__init__.py").is_file()
+            } else {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| self.extensions.iter().any(|e| e == ext))
+            };
+            if is_package {
+                local_packages.push(path.to_path_buf());
+            }
         }
         Ok(local_packages)
     }
@@ -51,25 +104,21 @@ impl PythonFileFinder {
         for entry in WalkDir::new(start_path)
             .follow_links(true)
             .into_iter()
-            .filter_entry(|e| {
-                // Skip excluded directories
-                if e.file_type().is_dir() {
-                    if let Some(dir_name) = e.file_name().to_str() {
-                        return !self.excluded_dirs.contains(&dir_name.to_string());
-                    }
-                }
-                true
-            })
+            .filter_entry(|e| !self.is_excluded(start_path, e))
             .filter_map(|e| e.ok())
         {
             let path = entry.path();
 
             if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    let ext = extension.to_str().unwrap_or("");
-                    if ext == "py" {
-                        python_files.push(path.to_path_buf());
-                    }
+                let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                let relative = path.strip_prefix(start_path).unwrap_or(path);
+                if self
+                    .extensions
+                    .iter()
+                    .any(|ext| file_name.ends_with(format!(".{ext}").as_str()))
+                    && !self.excluded_files.iter().any(|f| f.is_match(relative))
+                {
+                    python_files.push(path.to_path_buf());
                 }
             }
         }
@@ -78,6 +127,56 @@ impl PythonFileFinder {
     }
 }
 
+/// The deepest directory containing every one of `paths`, compared
+/// component by component without touching the filesystem
+pub fn common_ancestor(paths: &[PathBuf]) -> PathBuf {
+    let Some((first, rest)) = paths.split_first() else {
+        return PathBuf::new();
+    };
+    let mut ancestor = first.clone();
+    for path in rest {
+        ancestor = ancestor
+            .components()
+            .zip(path.components())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect();
+    }
+    ancestor
+}
+
+/// A directory or file excluded from the search, by name or by path
+struct ExcludedPath {
+    glob: GlobMatcher,
+    by_path: bool,
+}
+
+impl ExcludedPath {
+    fn new(pattern: &str) -> Self {
+        let pattern = pattern.trim_end_matches('/');
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .unwrap_or_else(|e| {
+                warn!("Excluding {pattern} by name only, it isn't a valid glob: {e}");
+                GlobBuilder::new(&globset::escape(pattern)).build().unwrap()
+            });
+        ExcludedPath {
+            glob: glob.compile_matcher(),
+            by_path: pattern.contains('/'),
+        }
+    }
+
+    fn is_match(&self, relative: &Path) -> bool {
+        match self.by_path {
+            true => self.glob.is_match(relative),
+            false => relative
+                .file_name()
+                .is_some_and(|name| self.glob.is_match(name)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -103,4 +202,167 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_python_file_finder_extra_extensions() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        File::create(temp_dir.path().join("app.py"))?;
+        File::create(temp_dir.path().join("settings.py.j2"))?;
+        File::create(temp_dir.path().join("notes.j2"))?;
+
+        let finder = PythonFileFinder::new();
+        let files = finder.find_files(&PathBuf::from(temp_dir.path()))?;
+        assert_eq!(files.len(), 1);
+
+        let finder = PythonFileFinder::new().extensions(vec!["py.j2".to_string()]);
+        let mut files = finder.find_files(&PathBuf::from(temp_dir.path()))?;
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                temp_dir.path().join("app.py"),
+                temp_dir.path().join("settings.py.j2")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_dirs_globs() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        for dir in [
+            "app",
+            "app/migrations",
+            "app/sub/migrations",
+            "build-linux",
+            "mypkg.egg-info",
+            "src/generated",
+            "vendor/src/generated",
+        ] {
+            fs::create_dir_all(temp_dir.path().join(dir))?;
+            File::create(temp_dir.path().join(dir).join("module.py"))?;
+        }
+
+        let finder = PythonFileFinder::new().exclude_dirs(vec![
+            "**/migrations".to_string(),
+            "build-*".to_string(),
+            "*.egg-info".to_string(),
+            "src/generated/".to_string(),
+        ]);
+        let mut files: Vec<PathBuf> = finder
+            .find_files(&PathBuf::from(temp_dir.path()))?
+            .iter()
+            .map(|f| f.strip_prefix(temp_dir.path()).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("app/module.py"),
+                // Paths are matched from the root of the search
+                PathBuf::from("vendor/src/generated/module.py"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_files_globs() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        for file in [
+            "app/api.py",
+            "app/proto/user_pb2.py",
+            "app/proto/user_pb2_grpc.py",
+            "vendor/six.py",
+            "app/vendor/six.py",
+        ] {
+            let path = temp_dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            File::create(path)?;
+        }
+
+        let finder = PythonFileFinder::new()
+            .exclude_files(vec!["*_pb2.py".to_string(), "vendor/six.py".to_string()]);
+        let mut files: Vec<PathBuf> = finder
+            .find_files(&PathBuf::from(temp_dir.path()))?
+            .iter()
+            .map(|f| f.strip_prefix(temp_dir.path()).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("app/api.py"),
+                PathBuf::from("app/proto/user_pb2_grpc.py"),
+                PathBuf::from("app/vendor/six.py"),
+            ]
+        );
+        Ok(())
+    }
+
+    fn local_package_names(finder: &PythonFileFinder, path: &std::path::Path) -> Vec<String> {
+        let mut names: Vec<String> = finder
+            .find_local_packages(&path.to_path_buf())
+            .unwrap()
+            .iter()
+            .skip(1) // the root package
+            .filter_map(|p| p.file_stem())
+            .filter_map(|n| n.to_str())
+            .map(String::from)
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn setup_packages() -> Result<tempfile::TempDir, io::Error> {
+        let temp_dir = tempdir()?;
+        // A regular package
+        fs::create_dir(temp_dir.path().join("regular"))?;
+        File::create(temp_dir.path().join("regular").join("__init__.py"))?;
+        // A PEP 420 namespace package
+        fs::create_dir(temp_dir.path().join("namespace"))?;
+        File::create(temp_dir.path().join("namespace").join("module.py"))?;
+        File::create(temp_dir.path().join("README.md"))?;
+        Ok(temp_dir)
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let paths = |ps: &[&str]| ps.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            common_ancestor(&paths(&["services/a", "services/b/src"])),
+            PathBuf::from("services")
+        );
+        assert_eq!(
+            common_ancestor(&paths(&["/repo/api"])),
+            PathBuf::from("/repo/api")
+        );
+        assert_eq!(
+            common_ancestor(&paths(&["/repo/api", "/repo/api/tests"])),
+            PathBuf::from("/repo/api")
+        );
+        assert_eq!(common_ancestor(&paths(&["api", "web"])), PathBuf::new());
+    }
+
+    #[test]
+    fn test_local_packages_require_init() -> Result<(), io::Error> {
+        let temp_dir = setup_packages()?;
+        let finder = PythonFileFinder::new();
+        assert_eq!(
+            local_package_names(&finder, temp_dir.path()),
+            vec!["__init__", "module", "regular"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_packages_with_namespace_packages() -> Result<(), io::Error> {
+        let temp_dir = setup_packages()?;
+        let finder = PythonFileFinder::new().namespace_packages(true);
+        assert_eq!(
+            local_package_names(&finder, temp_dir.path()),
+            vec!["__init__", "module", "namespace", "regular"]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,70 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Run git in `dir` and return what it prints to stdout
+fn git(dir: &Path, args: &[&str]) -> Result<String, io::Error> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Run git in `dir` and return the paths it prints, one per line, joined onto `dir`
+fn git_paths(dir: &Path, args: &[&str]) -> Result<Vec<PathBuf>, io::Error> {
+    Ok(git(dir, args)?
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| dir.join(l))
+        .collect())
+}
+
+/// Files under `dir` that were added relative to `git_ref`, including
+/// files that are new and not yet tracked
+pub fn added_files(dir: &Path, git_ref: &str) -> Result<Vec<PathBuf>, io::Error> {
+    let mut files = git_paths(
+        dir,
+        &[
+            "diff",
+            "--name-only",
+            "--relative",
+            "--diff-filter=A",
+            git_ref,
+        ],
+    )?;
+    files.extend(git_paths(
+        dir,
+        &["ls-files", "--others", "--exclude-standard"],
+    )?);
+    Ok(files)
+}
+
+/// Files under `dir` that were added or modified relative to `git_ref`,
+/// including files that are new and not yet tracked
+pub fn changed_files(dir: &Path, git_ref: &str) -> Result<Vec<PathBuf>, io::Error> {
+    let mut files = git_paths(
+        dir,
+        &[
+            "diff",
+            "--name-only",
+            "--relative",
+            "--diff-filter=ACMR",
+            git_ref,
+        ],
+    )?;
+    files.extend(git_paths(
+        dir,
+        &["ls-files", "--others", "--exclude-standard"],
+    )?);
+    Ok(files)
+}
+
+/// The most recent tag reachable from HEAD
+pub fn latest_tag(dir: &Path) -> Result<String, io::Error> {
+    Ok(git(dir, &["describe", "--tags", "--abbrev=0"])?
+        .trim()
+        .to_string())
+}
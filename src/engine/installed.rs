@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::{debug, warn};
+
+use crate::dependency::normalize_name;
+
+/// Versions of the packages installed in a site-packages directory, read
+/// from the METADATA of every `*.dist-info` in it
+#[derive(Debug, Default)]
+pub struct InstalledPackages {
+    versions: HashMap<String, String>,
+}
+
+impl InstalledPackages {
+    pub fn read(site_packages: &Path) -> Result<Self, io::Error> {
+        let mut versions = HashMap::new();
+        for entry in fs::read_dir(site_packages)? {
+            let path = entry?.path();
+            let is_dist_info = path.extension().is_some_and(|e| e == "dist-info");
+            if !is_dist_info || !path.is_dir() {
+                continue;
+            }
+            let metadata = match fs::read_to_string(path.join("METADATA")) {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!("Could not read the metadata in {path:?}");
+                    debug!("Error {e}");
+                    continue;
+                }
+            };
+            if let Some((name, version)) = name_and_version(&metadata) {
+                versions.insert(normalize_name(&name), version);
+            }
+        }
+        debug!(
+            "Found {} installed packages in {site_packages:?}",
+            versions.len()
+        );
+        Ok(InstalledPackages { versions })
+    }
+
+    /// The installed version of a package, if it's installed
+    pub fn version(&self, name: &str) -> Option<&str> {
+        self.versions.get(&normalize_name(name)).map(String::as_str)
+    }
+}
+
+// The Name and Version headers of a core metadata file. Headers end at the
+// first blank line, everything after it is the package description
+fn name_and_version(metadata: &str) -> Option<(String, String)> {
+    let mut name = None;
+    let mut version = None;
+    for line in metadata.lines().take_while(|l| !l.trim().is_empty()) {
+        if let Some(value) = line.strip_prefix("Name:") {
+            name = Some(value.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("Version:") {
+            version = Some(value.trim().to_string());
+        }
+    }
+    Some((name?, version?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::site_packages;
+
+    #[test]
+    fn test_reads_installed_versions() {
+        let dir = site_packages(&[("Django", "4.2.7"), ("typing-extensions", "4.8.0")]);
+        // Not a dist-info, ignored
+        fs::create_dir(dir.path().join("django")).unwrap();
+        let installed = InstalledPackages::read(dir.path()).unwrap();
+        assert_eq!(installed.version("django"), Some("4.2.7"));
+        assert_eq!(installed.version("typing_extensions"), Some("4.8.0"));
+        assert_eq!(installed.version("Typing.Extensions"), Some("4.8.0"));
+        assert_eq!(installed.version("not-a-header"), None);
+        assert_eq!(installed.version("flask"), None);
+    }
+
+    #[test]
+    fn test_missing_site_packages() {
+        assert!(InstalledPackages::read(Path::new("/does/not/exist")).is_err());
+    }
+}
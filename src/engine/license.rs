@@ -0,0 +1,162 @@
+use regex::{Regex, RegexBuilder};
+use serde_json::Value;
+use thiserror::Error;
+
+/// Which licenses newly added packages may have. Patterns are regexes matched
+/// case-insensitively against every license a package declares
+#[derive(Debug, Default)]
+pub struct LicensePolicy {
+    allow: Vec<Regex>,
+    deny: Vec<Regex>,
+    warn_only: bool,
+}
+
+#[derive(Debug, Error)]
+#[error("invalid license pattern {0}: {1}")]
+pub struct LicensePatternError(String, regex::Error);
+
+impl LicensePolicy {
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self, LicensePatternError> {
+        Ok(LicensePolicy {
+            allow: compile(allow)?,
+            deny: compile(deny)?,
+            warn_only: false,
+        })
+    }
+
+    /// Only warn about violations instead of failing the run
+    pub fn warn_only(mut self, warn_only: bool) -> Self {
+        self.warn_only = warn_only;
+        self
+    }
+
+    pub fn is_warn_only(&self) -> bool {
+        self.warn_only
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Why a package declaring `licenses` breaks the policy, if it does
+    pub fn violation(&self, licenses: &[String]) -> Option<String> {
+        for pattern in &self.deny {
+            if let Some(license) = licenses.iter().find(|l| pattern.is_match(l)) {
+                return Some(format!(
+                    "license {license} matches denied pattern {}",
+                    pattern.as_str()
+                ));
+            }
+        }
+        if self.allow.is_empty() {
+            return None;
+        }
+        if licenses.is_empty() {
+            return Some("license is unknown and an allow list is set".to_string());
+        }
+        let allowed = licenses
+            .iter()
+            .any(|l| self.allow.iter().any(|p| p.is_match(l)));
+        if allowed {
+            None
+        } else {
+            Some(format!(
+                "license {} doesn't match any allowed pattern",
+                licenses.join(", ")
+            ))
+        }
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Regex>, LicensePatternError> {
+    patterns
+        .iter()
+        .map(|p| {
+            RegexBuilder::new(p)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| LicensePatternError(p.clone(), e))
+        })
+        .collect()
+}
+
+/// Every license a package declares in its JSON API metadata: the SPDX
+/// `license_expression`, the `license` field and any `License ::` classifiers
+pub fn licenses_from_metadata(json: &str) -> Option<Vec<String>> {
+    let metadata: Value = serde_json::from_str(json).ok()?;
+    let info = metadata.get("info")?;
+    let mut licenses = Vec::new();
+    for field in ["license_expression", "license"] {
+        // The license field sometimes holds the whole license text, the
+        // first line is usually enough to tell which license it is
+        if let Some(line) = info
+            .get(field)
+            .and_then(Value::as_str)
+            .and_then(|l| l.lines().map(str::trim).find(|l| !l.is_empty()))
+        {
+            licenses.push(line.to_string());
+        }
+    }
+    let classifiers = info.get("classifiers").and_then(Value::as_array);
+    for classifier in classifiers.into_iter().flatten().filter_map(Value::as_str) {
+        if let Some(license) = classifier.strip_prefix("License :: ") {
+            licenses.push(license.rsplit(" :: ").next().unwrap_or(license).to_string());
+        }
+    }
+    Some(licenses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn licenses(input: &[&str]) -> Vec<String> {
+        input.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_violation() {
+        let policy = LicensePolicy::new(&[], &["^(A|L)?GPL".to_string()]).unwrap();
+        assert!(policy.violation(&licenses(&["GPL-3.0-only"])).is_some());
+        assert!(policy.violation(&licenses(&["MIT", "lgpl-2.1"])).is_some());
+        assert!(policy.violation(&licenses(&["MIT"])).is_none());
+        assert!(policy.violation(&[]).is_none());
+
+        let policy = LicensePolicy::new(&["MIT".to_string(), "Apache".to_string()], &[]).unwrap();
+        assert!(policy.violation(&licenses(&["MIT License"])).is_none());
+        assert!(policy.violation(&licenses(&["BSD-3-Clause"])).is_some());
+        // Can't tell whether an unknown license is allowed
+        assert!(policy.violation(&[]).is_some());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let err = LicensePolicy::new(&["MIT".to_string()], &["(GPL".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("(GPL"), "{err}");
+    }
+
+    #[test]
+    fn test_licenses_from_metadata() {
+        let json = r#"{"info": {
+            "license": "Copyright (c) 2024\n\nPermission is hereby granted...",
+            "license_expression": "GPL-3.0-or-later",
+            "classifiers": [
+                "Programming Language :: Python :: 3",
+                "License :: OSI Approved :: GNU General Public License v3 (GPLv3)"
+            ]
+        }}"#;
+        assert_eq!(
+            licenses_from_metadata(json).unwrap(),
+            licenses(&[
+                "GPL-3.0-or-later",
+                "Copyright (c) 2024",
+                "GNU General Public License v3 (GPLv3)"
+            ])
+        );
+        assert_eq!(
+            licenses_from_metadata(r#"{"info": {"license": null}}"#).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(licenses_from_metadata("<html>").is_none());
+    }
+}
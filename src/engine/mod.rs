@@ -1,46 +1,212 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::fs::read;
-use std::path::PathBuf;
-use std::str::from_utf8;
+use std::fs::{self, canonicalize, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{io, thread};
 
+use auth::Netrc;
+pub use cache::default_cache_path;
+use cache::IndexCache;
 use evaluator::DependencyEvaluator;
+pub use finder::common_ancestor;
 use finder::PythonFileFinder;
-use log::{debug, info};
-use parser::extract_dependencies;
+use installed::InstalledPackages;
+pub use license::LicensePatternError;
+use license::LicensePolicy;
+use log::{debug, error, info, warn};
+use parser::{decode_source, notebook_source, strip_jinja};
+pub use parser::{extract_dependencies, Import, ParseError};
+use progress::Progress;
+use proxy::{proxy_from_env, NoProxy};
+use regex::Regex;
 use resolver::PackageResolver;
+pub use resolver::VersionStrategy;
 use thiserror::Error;
 
 use crate::dependency::Dependency;
 use crate::pyproject::PyProject;
 
+mod auth;
+mod cache;
 mod evaluator;
 mod finder;
+mod git;
+mod installed;
 mod irregulars;
+mod license;
+mod namespaces;
 mod parser;
+mod pool;
+mod progress;
+mod proxy;
+mod rate_limiter;
 mod resolver;
+mod snapshot;
 mod stdlib;
+#[cfg(test)]
+mod testing;
+
+type ImportParser = fn(&str) -> Result<Vec<Import>, ParseError>;
+
+/// Packages resolved at once when the options don't say
+const DEFAULT_MAX_CONCURRENCY: usize = 8;
 
-type ImportParser = fn(&str) -> Result<Vec<String>, io::Error>;
+/// Directories that are never scanned unless the options opt them back in
+pub const DEFAULT_EXCLUDE_DIRS: &[&str] = &[".venv", ".git", "target"];
 
-#[derive(Clone, Debug, PartialEq)]
+/// How a [`DetectEngine`] scans a project and resolves what it finds, the
+/// defaults scan `.py` files and resolve on PyPI
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct EngineOptions {
+    /// Excluded along with [`DEFAULT_EXCLUDE_DIRS`]
     pub exclude_dirs: Vec<String>,
+    /// Files to skip, as globs matching their name or their path
+    pub exclude_files: Vec<String>,
+    /// Directories of [`DEFAULT_EXCLUDE_DIRS`] to scan anyway
+    pub include_dirs: Vec<String>,
+    /// Only exclude `exclude_dirs`
+    pub no_default_excludes: bool,
     pub extra_indexes: Vec<String>,
     pub preferred_index: Option<String>,
     pub extras_to_remap: HashMap<String, String>,
+    pub only_new_files: Option<String>,
+    pub since_tag: bool,
+    pub rate_limit: Option<f64>,
+    pub max_attempts: Option<u32>,
+    pub max_concurrency: Option<usize>,
+    pub timeout_secs: Option<u64>,
+    pub version_strategy: Option<VersionStrategy>,
+    pub allow_prerelease: bool,
+    /// Where resolved versions are cached between runs, no caching when None
+    pub cache_path: Option<PathBuf>,
+    pub cache_ttl_secs: Option<u64>,
+    /// Skip version resolution, new dependencies are added without a version
+    pub offline: bool,
+    pub extensions: Vec<String>,
+    /// Scan the code cells of Jupyter notebooks too
+    pub include_notebooks: bool,
+    pub treat_namespace_dirs_as_local: bool,
+    pub strict: bool,
+    /// Skip files bigger than this many KB rather than reading them, no limit when None
+    pub max_file_size_kb: Option<u64>,
+    pub allow_licenses: Vec<String>,
+    pub deny_licenses: Vec<String>,
+    pub warn_on_license_violation: bool,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Proxy for index requests, from `HTTPS_PROXY` and friends when None
+    pub proxy: Option<String>,
+    pub from_installed: Option<PathBuf>,
+    pub candidate_ignore_regexes: Vec<String>,
+    /// Ask the indexes about declared dependencies nothing imports, to catch
+    /// import names declared in place of package names
+    pub find_unused: bool,
+    /// Show how many packages have been resolved on stderr
+    pub progress: bool,
 }
 
+/// Scans a project's code for imports and works out which packages they
+/// need that its pyproject.toml doesn't declare, with the versions to add
 pub struct DetectEngine<'a> {
     pyproject: PyProject,
     finder: PythonFileFinder,
+    only_new_files: Option<String>,
+    since_tag: bool,
+    strict: bool,
+    max_file_size: Option<u64>,
     parser: ImportParser,
     evaluator: DependencyEvaluator<'a>,
     resolver: PackageResolver,
+    max_concurrency: usize,
+    find_unused: bool,
+    progress: bool,
+}
+
+/// Import candidates found while scanning, each with the files and lines
+/// importing it, along with the files that couldn't be read
+#[derive(Debug, Default)]
+struct Scan {
+    sources: HashMap<String, Vec<(PathBuf, usize)>>,
+    /// Candidates imported outside of `if TYPE_CHECKING:` at least once
+    runtime: HashSet<String>,
+    /// Candidates imported outside of a `try` catching `ImportError` at least once
+    required: HashSet<String>,
+    /// The marker of the `sys.version_info` checks every import of a
+    /// candidate is guarded by, None when one isn't or they differ
+    markers: HashMap<String, Option<String>>,
+    unreadable: Vec<PathBuf>,
+}
+
+impl Scan {
+    fn candidates(&self) -> HashSet<String> {
+        self.sources.keys().cloned().collect()
+    }
+
+    fn add_marker(&mut self, candidate: &str, marker: Option<&String>) {
+        add_common_marker(&mut self.markers, candidate, marker);
+    }
+}
+
+// Record another import's marker, only one all the imports share is kept
+fn add_common_marker(
+    markers: &mut HashMap<String, Option<String>>,
+    key: &str,
+    marker: Option<&String>,
+) {
+    match markers.get_mut(key) {
+        Some(common) if common.as_ref() != marker => *common = None,
+        Some(_) => {}
+        None => {
+            markers.insert(key.to_string(), marker.cloned());
+        }
+    }
+}
+
+/// A dependency detected in the code, with the files, relative to the
+/// scanned directory, and 1-based lines that import it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedDependency {
+    pub dependency: Dependency,
+    pub sources: Vec<(PathBuf, usize)>,
+    /// Only imported under `if TYPE_CHECKING:`, so not needed at runtime
+    pub type_checking_only: bool,
+    /// Only imported in `try` blocks catching `ImportError`, the code works
+    /// without it
+    pub optional_only: bool,
+}
+
+impl DetectedDependency {
+    /// The files importing the dependency, each once
+    pub fn files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = self.sources.iter().map(|(f, _)| f.as_path()).collect();
+        files.dedup();
+        files
+    }
+}
+
+/// What a scan found: the missing dependencies and the declared dependencies
+/// that are imported, each with the files importing it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Detection {
+    pub missing: Vec<DetectedDependency>,
+    pub declared: Vec<DetectedDependency>,
+    /// Missing dependencies no index, installed package or snapshot had a
+    /// version for, they're still in `missing` without one
+    pub unresolved: Vec<Dependency>,
+    /// Declared runtime dependencies no import maps to, only known after a
+    /// full scan
+    pub unused: Vec<Dependency>,
+    pub files_scanned: usize,
 }
 
 #[derive(Debug, Error)]
 pub enum DetectEngineError {
+    #[error("problem loading the TLS client certificate: {0}")]
+    ClientCert(String),
+    #[error("invalid proxy url: {0}")]
+    Proxy(String),
     #[allow(dead_code)]
     #[error("problem evaluating imports")]
     Evaluation,
@@ -48,69 +214,163 @@ pub enum DetectEngineError {
     FileFinding,
     #[error("problem reading python file")]
     FileReading,
-    #[error("problem parsing python code")]
-    Parsing,
+    #[error("{0} new packages violate the license policy")]
+    LicensePolicy(usize),
+    #[error("invalid candidate ignore pattern {0}: {1}")]
+    IgnorePattern(String, regex::Error),
+    #[error(transparent)]
+    LicensePattern(#[from] LicensePatternError),
+    #[error("problem reading installed packages: {0}")]
+    Installed(String),
+    #[error("problem reading changes from git: {0}")]
+    Git(String),
+    #[error("problem parsing python code in {}, {source}", path.display())]
+    Parsing { path: PathBuf, source: ParseError },
     #[allow(dead_code)]
     #[error("problem resolving packages on package index")]
     Resolver,
 }
 
+// The defaults that weren't opted back in, then the configured excludes
+fn excluded_dirs(options: &EngineOptions) -> Vec<String> {
+    let defaults = match options.no_default_excludes {
+        true => &[][..],
+        false => DEFAULT_EXCLUDE_DIRS,
+    };
+    defaults
+        .iter()
+        .filter(|dir| !options.include_dirs.iter().any(|d| d == *dir))
+        .map(|dir| dir.to_string())
+        .chain(options.exclude_dirs.iter().cloned())
+        .collect()
+}
+
 impl DetectEngine<'_> {
-    pub fn new(pyproject: PyProject, options: EngineOptions) -> Self {
-        let mut exclude_dirs = vec![
-            ".venv".to_string(),
-            ".git".to_string(),
-            "target".to_string(),
-        ];
-        for dir in &options.exclude_dirs {
-            exclude_dirs.push(dir.clone());
-        }
-        let resolver = PackageResolver::new(
+    pub fn new(pyproject: PyProject, options: EngineOptions) -> Result<Self, DetectEngineError> {
+        let exclude_dirs = excluded_dirs(&options);
+        let max_concurrency = options.max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY);
+        let mut resolver = PackageResolver::new(
             options.extra_indexes.clone(),
             options.preferred_index.clone(),
+        )
+        .rate_limit(options.rate_limit)
+        .max_attempts(options.max_attempts)
+        .timeout(options.timeout_secs.map(Duration::from_secs))
+        .connections(max_concurrency)
+        .version_strategy(options.version_strategy)
+        .requires_python(pyproject.requires_python())
+        .allow_prerelease(options.allow_prerelease)
+        .offline(options.offline)
+        .cache(options.cache_path.as_deref().map(|path| {
+            let ttl = options
+                .cache_ttl_secs
+                .map_or(cache::DEFAULT_TTL, Duration::from_secs);
+            IndexCache::open(path, ttl)
+        }))
+        .netrc(Netrc::from_env())
+        .no_proxy(NoProxy::from_env())
+        .license_policy(
+            LicensePolicy::new(&options.allow_licenses, &options.deny_licenses)?
+                .warn_only(options.warn_on_license_violation),
         );
-        let evaluator = DependencyEvaluator::new(options.extras_to_remap);
-        DetectEngine {
+        let proxy = options.proxy.clone().or_else(proxy_from_env);
+        resolver = resolver
+            .proxy(proxy.as_deref())
+            .map_err(|e| DetectEngineError::Proxy(e.to_string()))?;
+        if let Some(cert) = &options.client_cert {
+            resolver = resolver
+                .client_cert(cert, options.client_key.as_deref())
+                .map_err(|e| DetectEngineError::ClientCert(e.to_string()))?;
+        }
+        if let Some(site_packages) = &options.from_installed {
+            let installed = InstalledPackages::read(site_packages)
+                .map_err(|e| DetectEngineError::Installed(format!("{site_packages:?}: {e}")))?;
+            resolver = resolver.installed(installed);
+        }
+        let ignore_patterns = options
+            .candidate_ignore_regexes
+            .iter()
+            .map(|p| Regex::new(p).map_err(|e| DetectEngineError::IgnorePattern(p.clone(), e)))
+            .collect::<Result<_, _>>()?;
+        let evaluator =
+            DependencyEvaluator::new(options.extras_to_remap).ignore_patterns(ignore_patterns);
+        let mut extensions = options.extensions;
+        if options.include_notebooks {
+            extensions.push("ipynb".to_string());
+        }
+        Ok(DetectEngine {
             pyproject,
-            finder: finder::PythonFileFinder::new().exclude_dirs(exclude_dirs),
+            finder: finder::PythonFileFinder::new()
+                .exclude_dirs(exclude_dirs)
+                .exclude_files(options.exclude_files)
+                .extensions(extensions)
+                .namespace_packages(options.treat_namespace_dirs_as_local),
+            only_new_files: options.only_new_files,
+            since_tag: options.since_tag,
+            strict: options.strict,
+            max_file_size: options.max_file_size_kb.map(|kb| kb * 1024),
             parser: extract_dependencies,
             evaluator,
             resolver,
-        }
+            max_concurrency,
+            find_unused: options.find_unused,
+            progress: options.progress,
+        })
     }
 
     pub fn detect_dependencies(
         &self,
         path: PathBuf,
     ) -> Result<HashSet<Dependency>, DetectEngineError> {
-        // Find python modules
+        Ok(self
+            .detect_with_sources(path)?
+            .into_iter()
+            .map(|d| d.dependency)
+            .collect())
+    }
+
+    /// Detect the missing dependencies along with the files importing each of them
+    pub fn detect_with_sources(
+        &self,
+        path: PathBuf,
+    ) -> Result<Vec<DetectedDependency>, DetectEngineError> {
+        Ok(self.detect(path)?.missing)
+    }
+
+    /// Detect the missing dependencies, and which files import the ones
+    /// that are already declared
+    pub fn detect(&self, path: PathBuf) -> Result<Detection, DetectEngineError> {
+        self.detect_all(&[path])
+    }
+
+    /// Like [`DetectEngine::detect`] over several source roots at once, the
+    /// imports of all of them checked against the one pyproject.toml
+    pub fn detect_all(&self, roots: &[PathBuf]) -> Result<Detection, DetectEngineError> {
+        // Find python modules, once each when roots overlap
         info!("Reading your code...");
-        let files = self.finder.find_files(&path);
-        if files.is_err() {
-            return Err(DetectEngineError::FileFinding);
+        let mut files = Vec::new();
+        let mut seen = HashSet::new();
+        for root in roots {
+            for file in self.find_files(root)? {
+                if seen.insert(canonicalize(&file).unwrap_or_else(|_| file.clone())) {
+                    files.push(file);
+                }
+            }
         }
+        // Sources are shown relative to the directory holding every root
+        let path = common_ancestor(roots);
 
         // Parse imports
         info!("Parsing imports...");
-        let mut candidates: HashSet<String> = HashSet::new();
-        for file in &files.unwrap() {
-            let contents = read(file);
-            if contents.is_err() {
-                return Err(DetectEngineError::FileReading);
-            }
-            let contents = contents.unwrap();
-
-            // Guaranteed to be utf8 from match read(&file) above
-            let content_str = from_utf8(&contents).unwrap();
-            let imports = (self.parser)(content_str);
-            if imports.is_err() {
-                return Err(DetectEngineError::Parsing);
-            }
-            for i in imports.unwrap() {
-                // filter out mod.sub.subsub  we only want mod here
-                candidates.insert(i.split(".").take(1).collect::<String>());
-            }
+        let scan = self.collect_candidates(&files)?;
+        if !scan.unreadable.is_empty() {
+            warn!(
+                "Skipped {} of {} files that couldn't be read",
+                scan.unreadable.len(),
+                files.len()
+            );
         }
+        let candidates = scan.candidates();
 
         debug!(
             "Candidates: {}",
@@ -120,28 +380,142 @@ impl DetectEngine<'_> {
                 .collect::<Vec<_>>()
                 .join(",")
         );
-        let local_packages = self.get_local_packages(&path)?;
+        let mut local_packages = HashSet::new();
+        for root in roots {
+            local_packages.extend(self.get_local_packages(root)?);
+        }
+
+        // Trace each package back to the imports of it, through the same
+        // remapping the evaluator applies. Keyed by normalized name, an import
+        // is the same whatever extras the package is declared with
+        let mut sources: HashMap<String, Vec<(PathBuf, usize)>> = HashMap::new();
+        // Packages imported at runtime anywhere, the rest only by type checkers
+        let mut runtime: HashSet<String> = HashSet::new();
+        // Packages imported without an `except ImportError:` fallback anywhere
+        let mut required: HashSet<String> = HashSet::new();
+        // Packages only imported under the same `sys.version_info` check
+        let mut markers: HashMap<String, Option<String>> = HashMap::new();
+        for (candidate, imports) in &scan.sources {
+            let package = self.evaluator.package_for(candidate);
+            if package.is_empty() || local_packages.contains(candidate) {
+                continue;
+            }
+            let Some(dep) = Dependency::parse(package) else {
+                continue;
+            };
+            if scan.runtime.contains(candidate) {
+                runtime.insert(dep.normalized_name());
+            }
+            if scan.required.contains(candidate) {
+                required.insert(dep.normalized_name());
+            }
+            let marker = scan.markers.get(candidate).cloned().flatten();
+            add_common_marker(&mut markers, &dep.normalized_name(), marker.as_ref());
+            sources.entry(dep.normalized_name()).or_default().extend(
+                imports
+                    .iter()
+                    .map(|(f, line)| (f.strip_prefix(&path).unwrap_or(f).to_path_buf(), *line)),
+            );
+        }
+        let with_sources = |dependency: Dependency| {
+            let mut sources = sources
+                .get(&dependency.normalized_name())
+                .cloned()
+                .unwrap_or_default();
+            sources.sort();
+            sources.dedup();
+            DetectedDependency {
+                type_checking_only: !runtime.contains(&dependency.normalized_name()),
+                optional_only: !required.contains(&dependency.normalized_name()),
+                dependency,
+                sources,
+            }
+        };
+        let declared: Vec<DetectedDependency> = self
+            .pyproject
+            .all_deps()
+            .into_iter()
+            .filter(|d| sources.contains_key(&d.normalized_name()))
+            .map(with_sources)
+            .collect();
+
+        // Only a full scan sees every import a declared dependency could match
+        let mut unused = Vec::new();
+        if self.only_new_files.is_none() && !self.since_tag {
+            if self.find_unused {
+                for name in self.unknown_declared(&candidates) {
+                    warn!("Declared dependency {name} isn't imported and no index knows it, is the name right?");
+                }
+            }
+            unused = self
+                .pyproject
+                .deps()
+                .iter()
+                .filter(|d| !sources.contains_key(&d.normalized_name()))
+                .cloned()
+                .collect();
+            unused.sort_by_key(|d| d.normalized_name());
+            for dep in &unused {
+                debug!(
+                    "{} is declared but none of {} is imported",
+                    dep.name(),
+                    self.evaluator.import_names_for(&dep.name()).join(", ")
+                );
+            }
+        }
 
         // Evaluate the imports, i.e filtering and remapping
         info!("Evaluating candidates...");
         let deps = self
             .evaluator
             .evaluate(candidates, self.pyproject.all_deps(), local_packages);
+        if deps.is_empty() {
+            info!("Nothing to resolve");
+            return Ok(Detection {
+                declared,
+                unused,
+                files_scanned: files.len(),
+                ..Detection::default()
+            });
+        }
 
-        // Resolve each candidate in their own thread, join the threads
-        // collect the resolved deps back into a hashset
+        // Resolve the candidates on a bounded number of threads so we never
+        // have more than max_concurrency requests open against the indexes
         info!("Resolving packages...");
-        let resolved_deps: HashSet<Dependency> = deps
+        // Remaps can carry their own markers, those win
+        let deps: Vec<Dependency> = deps
             .into_iter()
-            .map(|dep| {
-                thread::spawn({
-                    let resolver = self.resolver.clone();
-                    move || resolver.resolve(&dep)
-                })
+            .map(|d| match markers.get(&d.normalized_name()) {
+                Some(Some(marker)) if d.markers().is_none() => d.with_markers(marker),
+                _ => d,
             })
-            .filter_map(|h| h.join().ok())
-            .filter_map(|result| result.ok())
             .collect();
+        let progress = Progress::new("Resolving packages", deps.len(), self.progress);
+        let results = pool::map_bounded(&deps, self.max_concurrency, |dep| {
+            let result = self.resolver.resolve(dep);
+            progress.inc();
+            result
+        });
+        progress.finish();
+        if let Err(e) = self.resolver.save_cache() {
+            warn!("Could not save the resolved versions to the cache: {e}");
+        }
+        let mut resolved_deps = HashSet::new();
+        let mut violations = 0;
+        for result in results {
+            match result {
+                Ok(dep) => {
+                    resolved_deps.insert(dep);
+                }
+                Err(e) => {
+                    error!("{e}");
+                    violations += 1;
+                }
+            }
+        }
+        if violations > 0 {
+            return Err(DetectEngineError::LicensePolicy(violations));
+        }
 
         debug!(
             "Resolved deps: {}",
@@ -152,7 +526,232 @@ impl DetectEngine<'_> {
                 .join(",")
         );
 
-        Ok(resolved_deps)
+        let mut unresolved: Vec<Dependency> = resolved_deps
+            .iter()
+            .filter(|d| self.resolver.pins_versions() && d.version_specs().is_empty())
+            .cloned()
+            .collect();
+        unresolved.sort_by_key(|d| d.normalized_name());
+
+        Ok(Detection {
+            missing: resolved_deps.into_iter().map(with_sources).collect(),
+            declared,
+            unresolved,
+            unused,
+            files_scanned: files.len(),
+        })
+    }
+
+    // Find the python files to scan, narrowed down to the files added since
+    // a git ref when running with --only-new-files, or to the files changed
+    // since the latest tag when running with --since-tag
+    fn find_files(&self, path: &PathBuf) -> Result<Vec<PathBuf>, DetectEngineError> {
+        let files = self.finder.find_files(path);
+        if files.is_err() {
+            return Err(DetectEngineError::FileFinding);
+        }
+        let files = files.unwrap();
+        let git_error = |e: io::Error| DetectEngineError::Git(e.to_string());
+        let changed = if let Some(git_ref) = &self.only_new_files {
+            let added = git::added_files(path, git_ref).map_err(git_error)?;
+            debug!("Found {} files added since {git_ref}", added.len());
+            added
+        } else if self.since_tag {
+            let tag = git::latest_tag(path).map_err(git_error)?;
+            let changed = git::changed_files(path, &tag).map_err(git_error)?;
+            info!("Scanning {} files changed since tag {tag}", changed.len());
+            changed
+        } else {
+            return Ok(files);
+        };
+        let changed: HashSet<PathBuf> = changed
+            .iter()
+            .filter_map(|f| canonicalize(f).ok())
+            .collect();
+        Ok(files
+            .into_iter()
+            .filter(|f| canonicalize(f).is_ok_and(|f| changed.contains(&f)))
+            .collect())
+    }
+
+    // Parse each file and collect the top level module of every import,
+    // splitting the files across one thread per available core.
+    // Files that can't be read are skipped unless running in strict mode
+    fn collect_candidates(&self, files: &[PathBuf]) -> Result<Scan, DetectEngineError> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = files.len().div_ceil(threads).max(1);
+        let results: Vec<Result<Scan, DetectEngineError>> = thread::scope(|s| {
+            let handles: Vec<_> = files
+                .chunks(chunk_size)
+                .map(|chunk| s.spawn(move || self.scan_files(chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("parser thread panicked"))
+                .collect()
+        });
+        let mut scan = Scan::default();
+        for result in results {
+            let chunk = result?;
+            for (candidate, files) in chunk.sources {
+                scan.sources.entry(candidate).or_default().extend(files);
+            }
+            scan.runtime.extend(chunk.runtime);
+            scan.required.extend(chunk.required);
+            for (candidate, marker) in chunk.markers {
+                scan.add_marker(&candidate, marker.as_ref());
+            }
+            scan.unreadable.extend(chunk.unreadable);
+        }
+        Ok(scan)
+    }
+
+    // Serially scan `files`, reusing a single read buffer between files
+    fn scan_files(&self, files: &[PathBuf]) -> Result<Scan, DetectEngineError> {
+        let mut scan = Scan::default();
+        let mut contents = Vec::new();
+        for file in files {
+            contents.clear();
+            // Imports are at the top, a huge file is generated code not worth reading
+            if let Some(max) = self.max_file_size {
+                if let Ok(metadata) = fs::metadata(file) {
+                    if metadata.len() > max {
+                        warn!(
+                            "Skipping {}, it's {} KB, over the {} KB max_file_size_kb",
+                            file.display(),
+                            metadata.len() / 1024,
+                            max / 1024
+                        );
+                        continue;
+                    }
+                }
+            }
+            if let Err(e) = File::open(file).and_then(|mut f| f.read_to_end(&mut contents)) {
+                if self.strict {
+                    return Err(DetectEngineError::FileReading);
+                }
+                warn!("Skipping {}, couldn't read it: {e}", file.display());
+                scan.unreadable.push(file.clone());
+                continue;
+            }
+
+            let Some(content_str) = decode_source(&contents) else {
+                if self.strict {
+                    return Err(DetectEngineError::FileReading);
+                }
+                warn!(
+                    "Skipping {}, it isn't UTF-8 and doesn't declare an encoding we can read",
+                    file.display()
+                );
+                scan.unreadable.push(file.clone());
+                continue;
+            };
+            // Jinja templates are only python once their tags are stripped out,
+            // and notebooks once their code cells are pulled out of the JSON
+            let is_template = file.extension().is_some_and(|ext| ext == "j2");
+            let is_notebook = file.extension().is_some_and(|ext| ext == "ipynb");
+            // Stubs are only ever read by type checkers
+            let is_stub = file.extension().is_some_and(|ext| ext == "pyi");
+            let source = if is_template {
+                Cow::Owned(strip_jinja(&content_str))
+            } else if is_notebook {
+                match notebook_source(&content_str) {
+                    Ok(source) => Cow::Owned(source),
+                    Err(_) if self.strict => return Err(DetectEngineError::FileReading),
+                    Err(e) => {
+                        warn!(
+                            "Skipping notebook {}, couldn't read its cells: {e}",
+                            file.display()
+                        );
+                        scan.unreadable.push(file.clone());
+                        continue;
+                    }
+                }
+            } else {
+                content_str
+            };
+            let imports = match (self.parser)(&source) {
+                Ok(imports) => imports,
+                Err(e) if is_template => {
+                    warn!(
+                        "Skipping template {}, not valid python after stripping jinja tags: {e}",
+                        file.display()
+                    );
+                    continue;
+                }
+                Err(e) if self.strict => {
+                    return Err(DetectEngineError::Parsing {
+                        path: file.clone(),
+                        source: e,
+                    });
+                }
+                Err(e) => {
+                    warn!("Skipping {}, couldn't parse it: {e}", file.display());
+                    continue;
+                }
+            };
+            for i in imports {
+                // Relative imports, `from . import x` or `from ..core import y`,
+                // are always local code, never a package to look up
+                if i.is_relative() || i.module.is_empty() {
+                    continue;
+                }
+                // filter out mod.sub.subsub  we only want mod here, unless mod
+                // is a namespace whose subpackages are separate distributions
+                let path = match &i.name {
+                    Some(name) => Cow::Owned(format!("{}.{name}", i.module)),
+                    None => Cow::Borrowed(&i.module),
+                };
+                let distribution = namespaces::distribution_for(&path);
+                let top_level = distribution
+                    .as_deref()
+                    .unwrap_or_else(|| i.module.split('.').next().unwrap_or_default());
+                let imports = match scan.sources.get_mut(top_level) {
+                    Some(imports) => imports,
+                    None => scan.sources.entry(top_level.to_string()).or_default(),
+                };
+                // `import a.b, a.c` is a single import of `a`
+                if imports.last() != Some(&(file.clone(), i.line)) {
+                    imports.push((file.clone(), i.line));
+                }
+                if !i.type_checking && !is_stub && !scan.runtime.contains(top_level) {
+                    scan.runtime.insert(top_level.to_string());
+                }
+                if !i.optional && !scan.required.contains(top_level) {
+                    scan.required.insert(top_level.to_string());
+                }
+                scan.add_marker(top_level, i.marker.as_ref());
+            }
+        }
+        Ok(scan)
+    }
+
+    // Declared runtime dependencies that no import maps to and that no index
+    // has, most likely an import name declared instead of the package name
+    fn unknown_declared(&self, candidates: &HashSet<String>) -> Vec<String> {
+        let imported: HashSet<String> = candidates
+            .iter()
+            .map(|c| self.evaluator.package_for(c))
+            .filter_map(Dependency::parse)
+            .map(|d| d.normalized_name())
+            .collect();
+        let unimported: Vec<&Dependency> = self
+            .pyproject
+            .deps()
+            .iter()
+            .filter(|d| !imported.contains(&d.normalized_name()))
+            .collect();
+        let known = pool::map_bounded(&unimported, self.max_concurrency, |d| {
+            self.resolver.is_known(d)
+        });
+        let mut unknown: Vec<String> = unimported
+            .iter()
+            .zip(known)
+            .filter(|(_, known)| !known)
+            .map(|(d, _)| d.name())
+            .collect();
+        unknown.sort();
+        unknown
     }
 
     // Get the local packages in the file tree and parse as a list of Strings that are "local packages"
@@ -186,16 +785,58 @@ mod tests {
 
     use super::*;
 
+    // Run git in `dir`, with an identity to commit as
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    }
+
     #[test]
     fn test_example_django_rest_app() -> Result<(), io::Error> {
         let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
         let options = EngineOptions {
             exclude_dirs: Vec::new(),
+            exclude_files: Vec::new(),
+            include_dirs: Vec::new(),
+            no_default_excludes: false,
             extra_indexes: Vec::new(),
             preferred_index: None,
             extras_to_remap: HashMap::new(),
+            only_new_files: None,
+            since_tag: false,
+            rate_limit: None,
+            max_attempts: None,
+            max_concurrency: None,
+            timeout_secs: None,
+            version_strategy: None,
+            allow_prerelease: false,
+            cache_path: None,
+            cache_ttl_secs: None,
+            offline: false,
+            extensions: Vec::new(),
+            include_notebooks: false,
+            treat_namespace_dirs_as_local: false,
+            strict: false,
+            max_file_size_kb: None,
+            allow_licenses: Vec::new(),
+            deny_licenses: Vec::new(),
+            warn_on_license_violation: false,
+            client_cert: None,
+            client_key: None,
+            proxy: None,
+            from_installed: None,
+            candidate_ignore_regexes: Vec::new(),
+            find_unused: false,
+            progress: false,
         };
-        let engine = DetectEngine::new(pyproject, options);
+        let engine = DetectEngine::new(pyproject, options).unwrap();
         let deps = engine
             .detect_dependencies(PathBuf::from("./example_app"))
             .unwrap();
@@ -204,4 +845,744 @@ mod tests {
         assert!(deps.contains(&Dependency::parse("djangorestframework").unwrap()));
         Ok(())
     }
+
+    #[test]
+    fn test_only_new_files_scans_files_added_since_ref() {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q"]);
+        std::fs::write(dir.path().join("old.py"), "import requests\n").unwrap();
+        git(dir.path(), &["add", "."]);
+        git(dir.path(), &["commit", "-qm", "initial"]);
+        git(dir.path(), &["tag", "base"]);
+        // Existing files that change keep being ignored, only new files count
+        std::fs::write(dir.path().join("old.py"), "import requests\nimport numpy\n").unwrap();
+        std::fs::write(dir.path().join("new.py"), "import flask\n").unwrap();
+        git(dir.path(), &["add", "new.py"]);
+
+        let options = EngineOptions {
+            only_new_files: Some("base".to_string()),
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(
+            pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap(),
+            options,
+        )
+        .unwrap();
+        let path = dir.path().to_path_buf();
+        let files = engine.find_files(&path).unwrap();
+        assert_eq!(files, vec![path.join("new.py")]);
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(scan.candidates(), HashSet::from(["flask".to_string()]));
+    }
+
+    #[test]
+    fn test_since_tag_scans_files_changed_since_latest_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q"]);
+        std::fs::write(dir.path().join("old.py"), "import requests\n").unwrap();
+        std::fs::write(dir.path().join("stable.py"), "import numpy\n").unwrap();
+        git(dir.path(), &["add", "."]);
+        git(dir.path(), &["commit", "-qm", "initial"]);
+        git(dir.path(), &["tag", "v1.0.0"]);
+        std::fs::write(
+            dir.path().join("stable.py"),
+            "import numpy\nimport pandas\n",
+        )
+        .unwrap();
+        git(dir.path(), &["commit", "-qam", "last release"]);
+        git(dir.path(), &["tag", "v1.1.0"]);
+        // Changes in this release cycle, both edits and new files count
+        std::fs::write(dir.path().join("old.py"), "import requests\nimport yaml\n").unwrap();
+        std::fs::write(dir.path().join("new.py"), "import flask\n").unwrap();
+        git(dir.path(), &["add", "."]);
+        git(dir.path(), &["commit", "-qm", "this release"]);
+
+        let options = EngineOptions {
+            since_tag: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(
+            pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap(),
+            options,
+        )
+        .unwrap();
+        let path = dir.path().to_path_buf();
+        let mut files = engine.find_files(&path).unwrap();
+        files.sort();
+        assert_eq!(files, vec![path.join("new.py"), path.join("old.py")]);
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(
+            scan.candidates(),
+            HashSet::from([
+                "flask".to_string(),
+                "requests".to_string(),
+                "yaml".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_scans_jinja_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("settings.py.j2"),
+            "{% if cache %}\nimport redis\n{% endif %}\nNAME = \"{{ name }}\"\n",
+        )
+        .unwrap();
+        // Not recoverable as python, skipped with a warning
+        std::fs::write(dir.path().join("broken.py.j2"), "import {{ pkg }}\n").unwrap();
+
+        let options = EngineOptions {
+            extensions: vec!["py.j2".to_string()],
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(
+            pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap(),
+            options,
+        )
+        .unwrap();
+        let files = engine.find_files(&dir.path().to_path_buf()).unwrap();
+        assert_eq!(files.len(), 2);
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(scan.candidates(), HashSet::from(["redis".to_string()]));
+    }
+
+    #[test]
+    fn test_skips_files_over_the_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.py"), "import requests\n").unwrap();
+        let generated = format!("import numpy\n{}", "TABLE = 1\n".repeat(300));
+        std::fs::write(dir.path().join("generated.py"), generated).unwrap();
+
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let options = EngineOptions {
+            max_file_size_kb: Some(2),
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject.clone(), options).unwrap();
+        let files = engine.find_files(&dir.path().to_path_buf()).unwrap();
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(scan.candidates(), HashSet::from(["requests".to_string()]));
+        assert!(scan.unreadable.is_empty());
+
+        let engine = DetectEngine::new(pyproject, EngineOptions::default()).unwrap();
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(scan.candidates().len(), 2);
+    }
+
+    #[test]
+    fn test_scans_stubs_and_windows_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.py"), "import requests\n").unwrap();
+        std::fs::write(
+            dir.path().join("app.pyi"),
+            "import requests\nfrom numpy.typing import NDArray\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("gui.pyw"), "import wx\n").unwrap();
+
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let engine = DetectEngine::new(pyproject.clone(), EngineOptions::default()).unwrap();
+        let files = engine.find_files(&dir.path().to_path_buf()).unwrap();
+        assert_eq!(files.len(), 1);
+
+        let options = EngineOptions {
+            extensions: vec!["pyi".to_string(), "pyw".to_string()],
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        let files = engine.find_files(&dir.path().to_path_buf()).unwrap();
+        assert_eq!(files.len(), 3);
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(
+            scan.candidates(),
+            HashSet::from([
+                "requests".to_string(),
+                "numpy".to_string(),
+                "wx".to_string()
+            ])
+        );
+        // Only imported by a stub, so only needed for type checking
+        assert_eq!(
+            scan.runtime,
+            HashSet::from(["requests".to_string(), "wx".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_skips_unreadable_files_unless_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let readable = dir.path().join("app.py");
+        std::fs::write(&readable, "import requests\n").unwrap();
+        // Disappeared between finding and reading
+        let missing = dir.path().join("gone.py");
+        // Latin-1 without a coding declaration
+        let undecodable = dir.path().join("legacy.py");
+        std::fs::write(&undecodable, b"import numpy\nname = '\xe9'\n").unwrap();
+        let declared = dir.path().join("declared.py");
+        std::fs::write(
+            &declared,
+            b"# coding: latin-1\nimport flask\nname = '\xe9'\n",
+        )
+        .unwrap();
+        let files = vec![missing.clone(), readable, undecodable.clone(), declared];
+
+        let mut options = EngineOptions::default();
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let engine = DetectEngine::new(pyproject.clone(), options.clone()).unwrap();
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(
+            scan.candidates(),
+            HashSet::from(["requests".to_string(), "flask".to_string()])
+        );
+        assert_eq!(scan.unreadable, vec![missing, undecodable]);
+
+        options.strict = true;
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        assert!(matches!(
+            engine.collect_candidates(&files),
+            Err(DetectEngineError::FileReading)
+        ));
+    }
+
+    #[test]
+    fn test_skips_broken_notebooks_unless_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let notebook = dir.path().join("analysis.ipynb");
+        std::fs::write(&notebook, "{\"cells\": [").unwrap();
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let mut options = EngineOptions {
+            include_notebooks: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject.clone(), options.clone()).unwrap();
+        let files = vec![notebook];
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(scan.unreadable, files);
+
+        options.strict = true;
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        assert!(matches!(
+            engine.collect_candidates(&files),
+            Err(DetectEngineError::FileReading)
+        ));
+    }
+
+    #[test]
+    fn test_skips_unparsable_files_unless_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let broken = dir.path().join("legacy.py");
+        std::fs::write(&broken, "import os\n\nprint 'python 2'\n").unwrap();
+        let readable = dir.path().join("app.py");
+        std::fs::write(&readable, "import requests\n").unwrap();
+        let files = vec![broken.clone(), readable];
+
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let engine = DetectEngine::new(pyproject.clone(), EngineOptions::default()).unwrap();
+        let scan = engine.collect_candidates(&files).unwrap();
+        assert_eq!(scan.candidates(), HashSet::from(["requests".to_string()]));
+
+        let options = EngineOptions {
+            strict: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        match engine.collect_candidates(&files) {
+            Err(DetectEngineError::Parsing { path, source }) => {
+                assert_eq!(path, broken);
+                assert_eq!(source.line, 3);
+            }
+            other => panic!("expected a parsing error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_scans_files_with_a_preamble() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("manage.py");
+        std::fs::write(
+            &file,
+            "#!/usr/bin/env python\n# -*- coding: utf-8 -*-\n\"\"\"Résumé tool\"\"\"\nimport requests\n",
+        )
+        .unwrap();
+
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let engine = DetectEngine::new(pyproject, EngineOptions::default()).unwrap();
+        let scan = engine.collect_candidates(&[file]).unwrap();
+        assert_eq!(scan.candidates(), HashSet::from(["requests".to_string()]));
+    }
+
+    #[test]
+    fn test_aliases_are_not_candidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.py");
+        std::fs::write(
+            &file,
+            "import numpy as np, pandas as pd\nimport matplotlib.pyplot as plt\nfrom requests import (\n    Session as S,\n    get,\n)\n",
+        )
+        .unwrap();
+
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let engine = DetectEngine::new(pyproject, EngineOptions::default()).unwrap();
+        let scan = engine.collect_candidates(&[file]).unwrap();
+        assert_eq!(
+            scan.candidates(),
+            HashSet::from([
+                "numpy".to_string(),
+                "pandas".to_string(),
+                "matplotlib".to_string(),
+                "requests".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_skips_relative_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("views.py");
+        std::fs::write(
+            &file,
+            "from . import utils\nfrom ..core import thing\nfrom .models import User\nimport httpx\n",
+        )
+        .unwrap();
+
+        let pyproject = pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap();
+        let engine = DetectEngine::new(pyproject, EngineOptions::default()).unwrap();
+        let scan = engine.collect_candidates(&[file]).unwrap();
+        assert_eq!(scan.candidates(), HashSet::from(["httpx".to_string()]));
+    }
+
+    #[test]
+    fn test_flags_unknown_declared_dependencies() {
+        use testing::{http_response, mock_index};
+
+        let (url, _) = mock_index(|path, _| match path {
+            "/simple/cv2" => http_response("404 Not Found", &[], ""),
+            _ => http_response("200 OK", &[], ""),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("pyproject.toml"),
+            "[project]\nname = \"app\"\ndependencies = [\"cv2\", \"gunicorn\", \"opencv-python\"]\n",
+        )
+        .unwrap();
+        let pyproject = pyproject::read(&dir.path().join("pyproject.toml")).unwrap();
+        let options = EngineOptions {
+            preferred_index: Some(format!("{url}/simple")),
+            extras_to_remap: HashMap::from([("cv2".to_string(), "opencv-python".to_string())]),
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        // cv2 is imported as cv2 but installed as opencv-python
+        let candidates = HashSet::from(["cv2".to_string()]);
+        assert_eq!(engine.unknown_declared(&candidates), vec!["cv2"]);
+    }
+
+    #[test]
+    fn test_parallel_scan_matches_serial() {
+        let dir = tempfile::tempdir().unwrap();
+        let modules = ["requests", "numpy", "flask", "os", "yaml", "attr"];
+        let files: Vec<PathBuf> = (0..200)
+            .map(|i| {
+                let path = dir.path().join(format!("mod_{i}.py"));
+                let a = modules[i % modules.len()];
+                let b = modules[(i * 7) % modules.len()];
+                std::fs::write(&path, format!("import {a}\nfrom {b}.sub import thing\n")).unwrap();
+                path
+            })
+            .collect();
+        let engine = DetectEngine::new(
+            pyproject::read(&PathBuf::from("./example_app/pyproject.toml")).unwrap(),
+            EngineOptions::default(),
+        )
+        .unwrap();
+        let parallel = engine.collect_candidates(&files).unwrap();
+        let serial = engine.scan_files(&files).unwrap();
+        assert_eq!(parallel.candidates(), serial.candidates());
+        assert_eq!(parallel.candidates().len(), modules.len());
+    }
+
+    #[test]
+    fn test_writes_groups_from_provenance() {
+        use crate::sections::{default_rules, SectionRules};
+        use testing::{http_response, mock_index};
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for (file, code) in [
+            ("src/app/__init__.py", ""),
+            ("src/app/api.py", "import httpx\nfrom app import models\n"),
+            (
+                "tests/test_api.py",
+                "import pytest\nimport httpx\nfrom app import api\n",
+            ),
+            ("docs/conf.py", "import furo\n"),
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, code).unwrap();
+        }
+        let pyproject_path = root.join("pyproject.toml");
+        std::fs::write(
+            &pyproject_path,
+            "[project]\nname = \"example\"\ndependencies = []\n",
+        )
+        .unwrap();
+
+        let (url, _) = mock_index(|path, _| {
+            let name = path.rsplit('/').next().unwrap();
+            let body = format!(r#"<a href="https://files/{name}-1.0.tar.gz">{name}</a>"#);
+            http_response("200 OK", &[], &body)
+        });
+        let options = EngineOptions {
+            preferred_index: Some(format!("{url}/simple")),
+            ..EngineOptions::default()
+        };
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let engine = DetectEngine::new(pyproject.clone(), options).unwrap();
+        let detected = engine.detect_with_sources(root.to_path_buf()).unwrap();
+        let httpx = detected
+            .iter()
+            .find(|d| d.dependency.name() == "httpx")
+            .unwrap();
+        assert_eq!(
+            httpx.sources,
+            vec![
+                (PathBuf::from("src/app/api.py"), 1),
+                (PathBuf::from("tests/test_api.py"), 2)
+            ]
+        );
+
+        let rules = SectionRules::new(&default_rules()).unwrap();
+        let by_section = rules.assign(&detected);
+        pyproject::write(
+            &pyproject_path,
+            pyproject,
+            by_section,
+            &pyproject::WriteOptions::default(),
+        )
+        .unwrap();
+        let written = std::fs::read_to_string(&pyproject_path).unwrap();
+        let doc = written.parse::<toml_edit::DocumentMut>().unwrap();
+        let names = |item: &toml_edit::Item| -> Vec<String> {
+            item.as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(names(&doc["project"]["dependencies"]), vec!["httpx~=1.0"]);
+        assert_eq!(
+            names(&doc["dependency-groups"]["test"]),
+            vec!["pytest~=1.0"]
+        );
+        assert_eq!(
+            names(&doc["project"]["optional-dependencies"]["docs"]),
+            vec!["furo~=1.0"]
+        );
+    }
+
+    #[test]
+    fn test_skips_resolution_when_nothing_to_resolve() {
+        use testing::{http_response, mock_index};
+
+        let dir = tempfile::tempdir().unwrap();
+        // Only stdlib and already declared imports
+        std::fs::write(
+            dir.path().join("app.py"),
+            "import os\nimport json\nimport django\n",
+        )
+        .unwrap();
+        let (url, log) = mock_index(|_, _| http_response("200 OK", &[], ""));
+        let options = EngineOptions {
+            preferred_index: Some(format!("{url}/simple")),
+            ..EngineOptions::default()
+        };
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(&pyproject_path, "[project]\ndependencies = [\"Django\"]\n").unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        let detected = engine
+            .detect_with_sources(dir.path().to_path_buf())
+            .unwrap();
+        assert!(detected.is_empty());
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_only_looks_up_unimported_dependencies_when_finding_unused() {
+        use testing::{http_response, mock_index};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.py"), "import os\n").unwrap();
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(
+            &pyproject_path,
+            "[project]\ndependencies = [\"corp-metrics\", \"corp-auth\"]\n",
+        )
+        .unwrap();
+        for find_unused in [false, true] {
+            let (url, log) = mock_index(|_, _| http_response("200 OK", &[], ""));
+            let options = EngineOptions {
+                preferred_index: Some(format!("{url}/simple")),
+                find_unused,
+                ..EngineOptions::default()
+            };
+            let pyproject = pyproject::read(&pyproject_path).unwrap();
+            let engine = DetectEngine::new(pyproject, options).unwrap();
+            engine.detect_all(&[dir.path().to_path_buf()]).unwrap();
+            let requests = log.lock().unwrap().len();
+            assert_eq!(requests, if find_unused { 2 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn test_reports_unresolved_dependencies() {
+        use testing::{http_response, mock_index};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("app.py"),
+            "import httpx\nimport notapackage_xyz\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("util.py"), "import os\n").unwrap();
+        let (url, _) = mock_index(|path, _| match path {
+            "/simple/httpx" => http_response(
+                "200 OK",
+                &[],
+                r#"<a href="https://files/httpx-0.28.1.tar.gz">httpx</a>"#,
+            ),
+            _ => http_response("404 Not Found", &[], ""),
+        });
+        let options = EngineOptions {
+            preferred_index: Some(format!("{url}/simple")),
+            cache_path: None,
+            ..EngineOptions::default()
+        };
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(&pyproject_path, "[project]\ndependencies = []\n").unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        let detection = engine.detect(dir.path().to_path_buf()).unwrap();
+        assert_eq!(detection.missing.len(), 2);
+        assert_eq!(
+            detection.unresolved,
+            vec![Dependency::parse("notapackage_xyz").unwrap()]
+        );
+        assert_eq!(detection.files_scanned, 2);
+    }
+
+    #[test]
+    fn test_reports_unused_declared_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.py"), "import django\nimport yaml\n").unwrap();
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(
+            &pyproject_path,
+            "[project]\ndependencies = [\"Django\", \"PyYAML\", \"requests\"]\n",
+        )
+        .unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let engine = DetectEngine::new(pyproject, EngineOptions::default()).unwrap();
+        let detection = engine.detect(dir.path().to_path_buf()).unwrap();
+        // yaml is installed as PyYAML
+        assert_eq!(
+            detection.unused,
+            vec![Dependency::parse("requests").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_default_excludes_can_be_opted_out_of() {
+        let options = EngineOptions {
+            exclude_dirs: vec!["build".to_string()],
+            exclude_files: Vec::new(),
+            ..EngineOptions::default()
+        };
+        assert_eq!(
+            excluded_dirs(&options),
+            vec![".venv", ".git", "target", "build"]
+        );
+        let options = EngineOptions {
+            include_dirs: vec!["target".to_string()],
+            ..options
+        };
+        assert_eq!(excluded_dirs(&options), vec![".venv", ".git", "build"]);
+        let options = EngineOptions {
+            no_default_excludes: true,
+            ..options
+        };
+        assert_eq!(excluded_dirs(&options), vec!["build"]);
+    }
+
+    #[test]
+    fn test_flags_type_checking_only_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("app.py"),
+            "from typing import TYPE_CHECKING\nif TYPE_CHECKING:\n    import pandas\n    import numpy\nimport numpy\n",
+        )
+        .unwrap();
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(&pyproject_path, "[project]\ndependencies = []\n").unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let options = EngineOptions {
+            offline: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        let detection = engine.detect(dir.path().to_path_buf()).unwrap();
+        let type_checking_only = |name: &str| {
+            detection
+                .missing
+                .iter()
+                .find(|d| d.dependency.name() == name)
+                .unwrap()
+                .type_checking_only
+        };
+        assert!(type_checking_only("pandas"));
+        // Imported at runtime too, runtime wins
+        assert!(!type_checking_only("numpy"));
+    }
+
+    #[test]
+    fn test_flags_optional_only_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("parsers.py"),
+            "try:\n    import lxml\nexcept ImportError:\n    lxml = None\ntry:\n    import numpy\nexcept ImportError:\n    numpy = None\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("app.py"), "import numpy\n").unwrap();
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(&pyproject_path, "[project]\ndependencies = []\n").unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let options = EngineOptions {
+            offline: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        let detection = engine.detect(dir.path().to_path_buf()).unwrap();
+        let optional_only = |name: &str| {
+            detection
+                .missing
+                .iter()
+                .find(|d| d.dependency.name() == name)
+                .unwrap()
+                .optional_only
+        };
+        assert!(optional_only("lxml"));
+        // Required somewhere else
+        assert!(!optional_only("numpy"));
+    }
+
+    #[test]
+    fn test_maps_namespace_packages_to_their_distributions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("app.py"),
+            "import os.path\nfrom google.cloud import storage, bigquery\nimport azure.storage.blob\n",
+        )
+        .unwrap();
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(&pyproject_path, "[project]\ndependencies = []\n").unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let options = EngineOptions {
+            offline: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        let detection = engine.detect(dir.path().to_path_buf()).unwrap();
+        let mut names: Vec<String> = detection
+            .missing
+            .iter()
+            .map(|d| d.dependency.name())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "azure-storage-blob",
+                "google-cloud-bigquery",
+                "google-cloud-storage"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detects_across_several_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("services/a"), dir.path().join("services/b"));
+        std::fs::create_dir_all(a.join("billing")).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        std::fs::write(a.join("billing/__init__.py"), "").unwrap();
+        std::fs::write(a.join("app.py"), "import numpy\nimport billing\n").unwrap();
+        std::fs::write(b.join("app.py"), "import numpy\nimport rich\n").unwrap();
+        // Not under either root
+        std::fs::write(dir.path().join("setup.py"), "import setuptools\n").unwrap();
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(&pyproject_path, "[project]\ndependencies = []\n").unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let options = EngineOptions {
+            offline: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        // Overlapping roots scan each file once
+        let detection = engine
+            .detect_all(&[a.clone(), b.clone(), b.join("app.py")])
+            .unwrap();
+        assert_eq!(detection.files_scanned, 3);
+        let mut missing: Vec<(String, Vec<(PathBuf, usize)>)> = detection
+            .missing
+            .into_iter()
+            .map(|d| (d.dependency.name(), d.sources))
+            .collect();
+        missing.sort();
+        assert_eq!(
+            missing,
+            [
+                (
+                    "numpy".to_string(),
+                    vec![
+                        (PathBuf::from("a/app.py"), 1),
+                        (PathBuf::from("b/app.py"), 1)
+                    ]
+                ),
+                ("rich".to_string(), vec![(PathBuf::from("b/app.py"), 2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_marks_dependencies_only_imported_for_some_pythons() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("compat.py"),
+            "import sys\nif sys.version_info < (3, 8):\n    import importlib_metadata\nif sys.version_info < (3, 11):\n    import tomli\n",
+        )
+        .unwrap();
+        // Imported for every Python somewhere else
+        std::fs::write(dir.path().join("config.py"), "import tomli\n").unwrap();
+        let pyproject_path = dir.path().join("pyproject.toml");
+        std::fs::write(&pyproject_path, "[project]\ndependencies = []\n").unwrap();
+        let pyproject = pyproject::read(&pyproject_path).unwrap();
+        let options = EngineOptions {
+            offline: true,
+            ..EngineOptions::default()
+        };
+        let engine = DetectEngine::new(pyproject, options).unwrap();
+        let detection = engine.detect(dir.path().to_path_buf()).unwrap();
+        let mut requirements: Vec<String> = detection
+            .missing
+            .iter()
+            .map(|d| d.dependency.to_dependency_repr())
+            .collect();
+        requirements.sort();
+        assert_eq!(
+            requirements,
+            ["importlib_metadata; python_version < '3.8'", "tomli"]
+        );
+    }
 }
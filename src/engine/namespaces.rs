@@ -0,0 +1,91 @@
+/// Modules of a namespace shipped by a distribution of their own, checked
+/// before [`NAMESPACES`]
+const NAMESPACE_MODULES: &[(&str, &str)] = &[
+    ("google.protobuf", "protobuf"),
+    ("google.auth", "google-auth"),
+    ("google.oauth2", "google-auth"),
+    ("google.api_core", "google-api-core"),
+    ("google.genai", "google-genai"),
+    ("google.generativeai", "google-generativeai"),
+    ("opentelemetry.trace", "opentelemetry-api"),
+    ("opentelemetry.metrics", "opentelemetry-api"),
+    ("opentelemetry.sdk", "opentelemetry-sdk"),
+];
+
+/// Namespace packages split across distributions, each subpackage comes from
+/// the prefix followed by its name, `google.cloud.storage` from
+/// `google-cloud-storage`. Longer namespaces come first
+const NAMESPACES: &[(&str, &str)] = &[
+    ("google.cloud", "google-cloud-"),
+    ("azure.mgmt", "azure-mgmt-"),
+    ("azure.storage", "azure-storage-"),
+    ("azure.keyvault", "azure-keyvault-"),
+    ("azure.ai", "azure-ai-"),
+    ("azure", "azure-"),
+    (
+        "opentelemetry.instrumentation",
+        "opentelemetry-instrumentation-",
+    ),
+    ("opentelemetry.exporter", "opentelemetry-exporter-"),
+    ("sphinxcontrib", "sphinxcontrib-"),
+    ("backports", "backports."),
+    ("jaraco", "jaraco."),
+    ("zope", "zope."),
+];
+
+/// The distribution providing a module of a known namespace package, None
+/// for modules outside of them and for a bare namespace like `google.cloud`
+pub fn distribution_for(module: &str) -> Option<String> {
+    let within = |namespace: &str| {
+        module == namespace
+            || module
+                .strip_prefix(namespace)
+                .is_some_and(|rest| rest.starts_with('.'))
+    };
+    if let Some((_, distribution)) = NAMESPACE_MODULES.iter().find(|(m, _)| within(m)) {
+        return Some(distribution.to_string());
+    }
+    let (namespace, prefix) = NAMESPACES.iter().find(|(n, _)| within(n))?;
+    let subpackage = module[namespace.len()..].split('.').nth(1)?;
+    match prefix.ends_with('-') {
+        true => Some(format!("{prefix}{}", subpackage.replace('_', "-"))),
+        false => Some(format!("{prefix}{subpackage}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_for() {
+        let distribution = |module: &str| distribution_for(module);
+        assert_eq!(
+            distribution("google.cloud.storage").as_deref(),
+            Some("google-cloud-storage")
+        );
+        assert_eq!(
+            distribution("google.cloud.storage_transfer.types").as_deref(),
+            Some("google-cloud-storage-transfer")
+        );
+        assert_eq!(
+            distribution("google.protobuf.json_format").as_deref(),
+            Some("protobuf")
+        );
+        assert_eq!(
+            distribution("azure.storage.blob").as_deref(),
+            Some("azure-storage-blob")
+        );
+        assert_eq!(
+            distribution("azure.identity").as_deref(),
+            Some("azure-identity")
+        );
+        assert_eq!(
+            distribution("zope.interface").as_deref(),
+            Some("zope.interface")
+        );
+        assert_eq!(distribution("google.cloud"), None);
+        assert_eq!(distribution("googleapiclient.discovery"), None);
+        assert_eq!(distribution("os.path"), None);
+    }
+}
@@ -1,39 +1,486 @@
+use regex::Regex;
+use rustpython_ast::Visitor;
+use rustpython_parser::ast::bigint::BigInt;
+use rustpython_parser::ast::{CmpOp, Constant, ExceptHandler, Expr, ExprCall, Stmt, StmtIf};
 use rustpython_parser::parse;
-use std::io;
-
-pub fn extract_dependencies(py_code: &str) -> Result<Vec<String>, io::Error> {
-    let ast = parse(py_code, rustpython_parser::Mode::Module, "<embedded>").unwrap();
-    let module = ast.module();
-    let mut imports = match &module {
-        Some(m) => m
-            .body
-            .iter()
-            .filter(|&s| s.clone().import_stmt().is_some())
-            .flat_map(|i| i.clone().import_stmt().unwrap().names)
-            .map(|i| i.name.to_string())
-            .collect(),
-        None => Vec::new(),
-    };
-    let from_imports = match &module {
-        Some(m) => m
-            .body
-            .iter()
-            .filter(|&s| s.clone().import_from_stmt().is_some())
-            .flat_map(|i| i.clone().import_from_stmt().unwrap().module)
-            .map(|i| i.to_string())
-            .collect(),
-        None => Vec::new(),
+use rustpython_parser::text_size::TextSize;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::str::from_utf8;
+use thiserror::Error;
+
+/// A single imported name, `import a.b as c` or `from ..a import b as c`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// The module as written, without leading dots, empty for `from . import x`
+    pub module: String,
+    /// The name imported from the module by a `from` import
+    pub name: Option<String>,
+    pub alias: Option<String>,
+    /// Number of leading dots of a relative import, 0 for absolute imports
+    pub level: u32,
+    /// 1-based line of the import statement
+    pub line: usize,
+    /// Imported by a call, `importlib.import_module("a")` or `__import__("a")`
+    pub dynamic: bool,
+    /// Under an `if TYPE_CHECKING:` guard, only type checkers need it
+    pub type_checking: bool,
+    /// The PEP 508 marker of the `sys.version_info` checks guarding it, e.g.
+    /// `python_version < '3.8'`
+    pub marker: Option<String>,
+    /// In the body of a `try` catching `ImportError`, the code runs without it
+    pub optional: bool,
+}
+
+impl Import {
+    pub fn is_relative(&self) -> bool {
+        self.level > 0
+    }
+}
+
+impl Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dots = ".".repeat(self.level as usize);
+        if self.dynamic {
+            return write!(f, "import_module(\"{dots}{}\")", self.module);
+        }
+        match &self.name {
+            Some(name) => write!(f, "from {dots}{} import {name}", self.module)?,
+            None => write!(f, "import {}", self.module)?,
+        }
+        if let Some(alias) = &self.alias {
+            write!(f, " as {alias}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Python that couldn't be parsed, with the 1-based line the parser gave up on
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("line {line}: {message}")]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Every import in the code, relative ones included so debug-imports can show
+/// them. The engine drops relative imports before looking packages up
+pub fn extract_dependencies(py_code: &str) -> Result<Vec<Import>, ParseError> {
+    // Byte offsets of every line start, to turn statement offsets into line numbers
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(py_code.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let line_of = |offset: TextSize| line_starts.partition_point(|&s| s <= offset.to_usize());
+
+    let ast =
+        parse(py_code, rustpython_parser::Mode::Module, "<embedded>").map_err(|e| ParseError {
+            line: line_of(e.offset),
+            message: e.error.to_string(),
+        })?;
+    let Some(module) = ast.module() else {
+        return Ok(Vec::new());
+    };
+
+    let mut imports = Vec::new();
+    collect_imports(&module.body, &line_of, &mut imports);
+    let mut dynamic = DynamicImports {
+        line_of: &line_of,
+        imports: Vec::new(),
     };
-    imports.extend(from_imports);
+    for stmt in module.body {
+        dynamic.visit_stmt(stmt);
+    }
+    imports.extend(dynamic.imports);
+    imports.sort_by_key(|i| i.line);
     Ok(imports)
 }
 
+// Collects the calls importing a module named by a string literal anywhere in
+// the code, except in blocks that can never run
+struct DynamicImports<'a, F> {
+    line_of: &'a F,
+    imports: Vec<Import>,
+}
+
+impl<F: Fn(TextSize) -> usize> Visitor for DynamicImports<'_, F> {
+    fn visit_stmt_if(&mut self, node: StmtIf) {
+        if !is_constant_false(&node.test) {
+            return self.generic_visit_stmt_if(node);
+        }
+        for stmt in node.orelse {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    fn visit_expr_call(&mut self, node: ExprCall) {
+        if let Some(import) = dynamic_import(&node, self.line_of) {
+            self.imports.push(import);
+        }
+        self.generic_visit_expr_call(node)
+    }
+}
+
+// The import made by `importlib.import_module("a.b")`, `import_module(".b", package)`
+// or `__import__("a")` when the module is a string literal
+fn dynamic_import(call: &ExprCall, line_of: &impl Fn(TextSize) -> usize) -> Option<Import> {
+    let function = match call.func.as_ref() {
+        Expr::Name(name) => name.id.as_str(),
+        Expr::Attribute(attr) => match attr.value.as_ref() {
+            Expr::Name(module) if module.id.as_str() == "importlib" => attr.attr.as_str(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    if function != "import_module" && function != "__import__" {
+        return None;
+    }
+    let Expr::Constant(arg) = call.args.first()? else {
+        return None;
+    };
+    let Constant::Str(target) = &arg.value else {
+        return None;
+    };
+    let module = target.trim_start_matches('.');
+    let level = (target.len() - module.len()) as u32;
+    if module.is_empty() && level == 0 {
+        return None;
+    }
+    Some(Import {
+        module: module.to_string(),
+        name: None,
+        alias: None,
+        level,
+        line: line_of(call.range.start()),
+        dynamic: true,
+        type_checking: false,
+        marker: None,
+        optional: false,
+    })
+}
+
+// Collect the imports in a block of statements, descending into nested
+// blocks (functions, classes, loops, try, with, match...) but skipping
+// blocks that can never run
+fn collect_imports(body: &[Stmt], line_of: &impl Fn(TextSize) -> usize, imports: &mut Vec<Import>) {
+    for stmt in body {
+        match stmt {
+            Stmt::Import(import) => {
+                let line = line_of(import.range.start());
+                imports.extend(import.names.iter().map(|alias| Import {
+                    module: alias.name.to_string(),
+                    name: None,
+                    alias: alias.asname.as_ref().map(|a| a.to_string()),
+                    level: 0,
+                    line,
+                    dynamic: false,
+                    type_checking: false,
+                    marker: None,
+                    optional: false,
+                }))
+            }
+            Stmt::ImportFrom(import) => {
+                let line = line_of(import.range.start());
+                let module = import
+                    .module
+                    .as_ref()
+                    .map(|m| m.to_string())
+                    .unwrap_or_default();
+                let level = import.level.map(|l| l.to_u32()).unwrap_or(0);
+                imports.extend(import.names.iter().map(|alias| Import {
+                    module: module.clone(),
+                    name: Some(alias.name.to_string()),
+                    alias: alias.asname.as_ref().map(|a| a.to_string()),
+                    level,
+                    line,
+                    dynamic: false,
+                    type_checking: false,
+                    marker: None,
+                    optional: false,
+                }))
+            }
+            Stmt::If(s) => {
+                let markers = version_markers(&s.test);
+                // `if False:` and friends are disabled code
+                if is_type_checking(&s.test) {
+                    let start = imports.len();
+                    collect_imports(&s.body, line_of, imports);
+                    for import in &mut imports[start..] {
+                        import.type_checking = true;
+                    }
+                } else if !is_constant_false(&s.test) {
+                    let start = imports.len();
+                    collect_imports(&s.body, line_of, imports);
+                    if let Some((marker, _)) = &markers {
+                        add_marker(&mut imports[start..], marker);
+                    }
+                }
+                let start = imports.len();
+                collect_imports(&s.orelse, line_of, imports);
+                if let Some((_, otherwise)) = &markers {
+                    add_marker(&mut imports[start..], otherwise);
+                }
+            }
+            Stmt::FunctionDef(s) => collect_imports(&s.body, line_of, imports),
+            Stmt::AsyncFunctionDef(s) => collect_imports(&s.body, line_of, imports),
+            Stmt::ClassDef(s) => collect_imports(&s.body, line_of, imports),
+            Stmt::With(s) => collect_imports(&s.body, line_of, imports),
+            Stmt::AsyncWith(s) => collect_imports(&s.body, line_of, imports),
+            Stmt::For(s) => {
+                collect_imports(&s.body, line_of, imports);
+                collect_imports(&s.orelse, line_of, imports);
+            }
+            Stmt::AsyncFor(s) => {
+                collect_imports(&s.body, line_of, imports);
+                collect_imports(&s.orelse, line_of, imports);
+            }
+            Stmt::While(s) => {
+                collect_imports(&s.body, line_of, imports);
+                collect_imports(&s.orelse, line_of, imports);
+            }
+            Stmt::Try(s) => {
+                let start = imports.len();
+                collect_imports(&s.body, line_of, imports);
+                if catches_import_error(&s.handlers) {
+                    for import in &mut imports[start..] {
+                        import.optional = true;
+                    }
+                }
+                for ExceptHandler::ExceptHandler(h) in &s.handlers {
+                    collect_imports(&h.body, line_of, imports);
+                }
+                collect_imports(&s.orelse, line_of, imports);
+                collect_imports(&s.finalbody, line_of, imports);
+            }
+            Stmt::TryStar(s) => {
+                let start = imports.len();
+                collect_imports(&s.body, line_of, imports);
+                if catches_import_error(&s.handlers) {
+                    for import in &mut imports[start..] {
+                        import.optional = true;
+                    }
+                }
+                for ExceptHandler::ExceptHandler(h) in &s.handlers {
+                    collect_imports(&h.body, line_of, imports);
+                }
+                collect_imports(&s.orelse, line_of, imports);
+                collect_imports(&s.finalbody, line_of, imports);
+            }
+            Stmt::Match(s) => {
+                for case in &s.cases {
+                    collect_imports(&case.body, line_of, imports);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Whether a `try` falls back when an import fails, one of its handlers
+// catching `ImportError` or `ModuleNotFoundError`, alone or in a tuple. A
+// bare `except:` could be there for anything, so none of them count then
+fn catches_import_error(handlers: &[ExceptHandler]) -> bool {
+    let is_import_error = |e: &Expr| match e {
+        Expr::Name(name) => matches!(name.id.as_str(), "ImportError" | "ModuleNotFoundError"),
+        _ => false,
+    };
+    let mut catches = false;
+    for ExceptHandler::ExceptHandler(h) in handlers {
+        match h.type_.as_deref() {
+            None => return false,
+            Some(Expr::Tuple(tuple)) => catches |= tuple.elts.iter().any(is_import_error),
+            Some(e) => catches |= is_import_error(e),
+        }
+    }
+    catches
+}
+
+// Whether an `if` test is `TYPE_CHECKING` or `typing.TYPE_CHECKING`, true
+// for type checkers only
+fn is_type_checking(test: &Expr) -> bool {
+    match test {
+        Expr::Name(name) => name.id.as_str() == "TYPE_CHECKING",
+        Expr::Attribute(attr) => {
+            attr.attr.as_str() == "TYPE_CHECKING"
+                && matches!(attr.value.as_ref(), Expr::Name(m) if m.id.as_str() == "typing" || m.id.as_str() == "typing_extensions")
+        }
+        _ => false,
+    }
+}
+
+// The markers for the two branches of an `if` comparing `sys.version_info`
+// to a (major, minor) tuple, e.g. `sys.version_info < (3, 8)`. Only the
+// major and minor versions are compared, `<= (3, 8)` is `< (3, 8)` for a
+// version_info like (3, 8, 10) and `> (3, 8)` is `>= (3, 8)`
+fn version_markers(test: &Expr) -> Option<(String, String)> {
+    let Expr::Compare(compare) = test else {
+        return None;
+    };
+    let ([op], [Expr::Tuple(tuple)]) = (compare.ops.as_slice(), compare.comparators.as_slice())
+    else {
+        return None;
+    };
+    let is_version_info = match compare.left.as_ref() {
+        Expr::Name(name) => name.id.as_str() == "version_info",
+        Expr::Attribute(attr) => {
+            attr.attr.as_str() == "version_info"
+                && matches!(attr.value.as_ref(), Expr::Name(m) if m.id.as_str() == "sys")
+        }
+        _ => false,
+    };
+    if !is_version_info || tuple.elts.is_empty() || tuple.elts.len() > 2 {
+        return None;
+    }
+    let parts: Vec<String> = tuple
+        .elts
+        .iter()
+        .map(|e| match e {
+            Expr::Constant(c) => match &c.value {
+                Constant::Int(i) => Some(i.to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    let version = parts.join(".");
+    let below = format!("python_version < '{version}'");
+    let from = format!("python_version >= '{version}'");
+    match op {
+        CmpOp::Lt | CmpOp::LtE => Some((below, from)),
+        CmpOp::Gt | CmpOp::GtE => Some((from, below)),
+        _ => None,
+    }
+}
+
+// Guard imports with another marker, on top of the ones of nested checks
+fn add_marker(imports: &mut [Import], marker: &str) {
+    for import in imports {
+        import.marker = Some(match import.marker.take() {
+            Some(inner) => format!("{marker} and {inner}"),
+            None => marker.to_string(),
+        });
+    }
+}
+
+// Whether an `if` test is a literal that's always false: False, 0 or None
+fn is_constant_false(test: &Expr) -> bool {
+    match test {
+        Expr::Constant(c) => match &c.value {
+            Constant::Bool(b) => !b,
+            Constant::Int(i) => *i == BigInt::from(0),
+            Constant::None => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Best effort conversion of a jinja template into python: statements and
+/// comments are dropped and expressions are replaced with `None`
+pub fn strip_jinja(template: &str) -> String {
+    let tags = Regex::new(r"(?s)\{%.*?%\}|\{#.*?#\}").unwrap();
+    let expressions = Regex::new(r"(?s)\{\{.*?\}\}").unwrap();
+    let without_tags = tags.replace_all(template, "");
+    expressions.replace_all(&without_tags, "None").into_owned()
+}
+
+/// The python in a Jupyter notebook, its code cells one after the other.
+/// IPython syntax isn't python: cells run by a `%%` cell magic are dropped
+/// and `%magic` and `!shell` lines are blanked
+pub fn notebook_source(notebook: &str) -> Result<String, serde_json::Error> {
+    let notebook: serde_json::Value = serde_json::from_str(notebook)?;
+    let mut source = String::new();
+    let cells = notebook["cells"].as_array().into_iter().flatten();
+    for cell in cells.filter(|c| c["cell_type"] == "code") {
+        // Sources are saved as a list of lines, or as a single string
+        let cell_source = match &cell["source"] {
+            serde_json::Value::Array(lines) => lines.iter().filter_map(|l| l.as_str()).collect(),
+            serde_json::Value::String(s) => s.clone(),
+            _ => String::new(),
+        };
+        if cell_source.trim_start().starts_with("%%") {
+            continue;
+        }
+        for line in cell_source.lines() {
+            let trimmed = line.trim_start();
+            if !trimmed.starts_with('%') && !trimmed.starts_with('!') {
+                source += line;
+            }
+            source.push('\n');
+        }
+    }
+    Ok(source)
+}
+
+/// Python source as text. Source that isn't UTF-8 is decoded as its PEP 263
+/// `# -*- coding: latin-1 -*-` declaration says, None when there isn't one or
+/// it names an encoding other than Latin-1
+pub fn decode_source(bytes: &[u8]) -> Option<Cow<'_, str>> {
+    if let Ok(source) = from_utf8(bytes) {
+        return Some(Cow::Borrowed(source));
+    }
+    // The declaration has to be a comment on the first or second line
+    let coding = Regex::new(r"^[ \t\x0c]*#.*?coding[:=][ \t]*([-\w.]+)").unwrap();
+    let encoding = bytes.split(|&b| b == b'\n').take(2).find_map(|line| {
+        let line = String::from_utf8_lossy(line);
+        coding
+            .captures(&line)
+            .map(|c| c[1].to_lowercase().replace('_', "-"))
+    })?;
+    match encoding.as_str() {
+        // cp1252 only differs in the printable characters it puts in 0x80-0x9f,
+        // which can't be part of an import
+        "latin-1" | "latin1" | "iso-8859-1" | "iso8859-1" | "l1" | "cp1252" | "windows-1252" => {
+            Some(Cow::Owned(bytes.iter().map(|&b| b as char).collect()))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_finds_both_types_of_imports() -> Result<(), io::Error> {
+    fn test_notebook_source() {
+        let notebook = r#"{
+            "cells": [
+                {"cell_type": "markdown", "source": ["import not_code\n"]},
+                {"cell_type": "code", "source": ["%matplotlib inline\n", "!pip install pandas\n", "import pandas as pd\n", "from sklearn import svm"]},
+                {"cell_type": "code", "source": "%%bash\npython -c 'import sh_only'"},
+                {"cell_type": "code", "source": "    %time import numpy\nimport torch\n"}
+            ],
+            "nbformat": 4
+        }"#;
+        let source = notebook_source(notebook).unwrap();
+        let modules: Vec<String> = extract_dependencies(&source)
+            .unwrap()
+            .into_iter()
+            .map(|i| i.module)
+            .collect();
+        assert_eq!(modules, vec!["pandas", "sklearn", "torch"]);
+        assert!(notebook_source("not json").is_err());
+    }
+
+    #[test]
+    fn test_decode_source() {
+        assert_eq!(
+            decode_source("import café\n".as_bytes()),
+            Some(Cow::Borrowed("import café\n"))
+        );
+        let latin1 = b"#!/usr/bin/env python\n# -*- coding: iso-8859-1 -*-\nimport requests\nname = '\xe9t\xe9'\n";
+        let source = decode_source(latin1).unwrap();
+        assert!(source.ends_with("name = '\u{e9}t\u{e9}'\n"));
+        assert_eq!(extract_dependencies(&source).unwrap()[0].module, "requests");
+        // Undeclared, or declared as something else
+        assert_eq!(decode_source(b"import requests\nname = '\xe9'\n"), None);
+        assert_eq!(
+            decode_source(b"# coding=shift_jis\nimport requests\nname = '\x82\xa0'\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_finds_both_types_of_imports() -> Result<(), ParseError> {
         let code = r#"
 from django import db
 import os
@@ -42,8 +489,341 @@ def is_odd(i):
 "#;
         let imports = extract_dependencies(code).unwrap();
         assert_eq!(imports.len(), 2); // Should only find test1.py
-        assert!(imports.contains(&String::from("django")));
-        assert!(imports.contains(&String::from("os")));
+        assert!(imports.iter().any(|i| i.module == "django"));
+        assert!(imports.iter().any(|i| i.module == "os"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_structured_imports() -> Result<(), ParseError> {
+        let code = "import os.path as p, sys\n\nfrom ..models import User as U, Group\nfrom . import views\n";
+        let imports = extract_dependencies(code)?;
+        assert_eq!(
+            imports[0],
+            Import {
+                module: "os.path".to_string(),
+                name: None,
+                alias: Some("p".to_string()),
+                level: 0,
+                line: 1,
+                dynamic: false,
+                type_checking: false,
+                marker: None,
+                optional: false,
+            }
+        );
+        assert_eq!(imports[1].module, "sys");
+        assert_eq!(
+            imports[2],
+            Import {
+                module: "models".to_string(),
+                name: Some("User".to_string()),
+                alias: Some("U".to_string()),
+                level: 2,
+                line: 3,
+                dynamic: false,
+                type_checking: false,
+                marker: None,
+                optional: false,
+            }
+        );
+        assert_eq!(imports[3].to_string(), "from ..models import Group");
+        assert_eq!(imports[4].to_string(), "from . import views");
+        assert_eq!(imports[4].line, 4);
+        assert!(imports[4].is_relative());
+        Ok(())
+    }
+
+    #[test]
+    fn test_aliases_and_multiple_names() -> Result<(), ParseError> {
+        let code =
+            "import a as x, b.c as y, d\nfrom pkg import (\n    a,\n    b as bee,\n    c,\n)\n";
+        let imports = extract_dependencies(code)?;
+        let found: Vec<(&str, Option<&str>, Option<&str>, usize)> = imports
+            .iter()
+            .map(|i| {
+                (
+                    i.module.as_str(),
+                    i.name.as_deref(),
+                    i.alias.as_deref(),
+                    i.line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("a", None, Some("x"), 1),
+                ("b.c", None, Some("y"), 1),
+                ("d", None, None, 1),
+                ("pkg", Some("a"), None, 2),
+                ("pkg", Some("b"), Some("bee"), 2),
+                ("pkg", Some("c"), None, 2),
+            ]
+        );
+        assert_eq!(imports[1].to_string(), "import b.c as y");
+        assert_eq!(imports[4].to_string(), "from pkg import b as bee");
+        Ok(())
+    }
+
+    #[test]
+    fn test_shebang_and_coding_cookie() -> Result<(), ParseError> {
+        let code = "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\nimport requests\n";
+        let imports = extract_dependencies(code)?;
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].module, "requests");
+        assert_eq!(imports[0].line, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_returns_error_on_invalid_syntax() {
+        let err = extract_dependencies("import os\n\nprint 'python 2'\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(extract_dependencies("import (").is_err());
+    }
+
+    #[test]
+    fn test_strip_jinja() -> Result<(), ParseError> {
+        let template = r#"
+{# Generated by the scaffolder #}
+import {{ package_name }}
+{% if use_redis %}
+import redis
+{% endif %}
+TIMEOUT = {{ timeout }}
+"#;
+        let code = strip_jinja(template);
+        assert!(!code.contains('{'));
+        assert!(code.contains("TIMEOUT = None"));
+        let imports = extract_dependencies(&code);
+        // `import None` isn't valid python, templated imports can't be recovered
+        assert!(imports.is_err());
+
+        let template = "{% if use_redis %}\nimport redis\n{% endif %}\nTIMEOUT = {{ timeout }}\n";
+        let imports = extract_dependencies(&strip_jinja(template))?;
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].module, "redis");
+        Ok(())
+    }
+
+    #[test]
+    fn test_finds_imports_in_nested_blocks() -> Result<(), ParseError> {
+        let code = r#"
+try:
+    import ujson as json
+except ImportError:
+    import json
+
+class Api:
+    def handler(self):
+        from requests import get
+        with open("f") as f:
+            for line in f:
+                import yaml
+
+match command:
+    case "serve":
+        import uvicorn
+"#;
+        let modules: Vec<String> = extract_dependencies(code)?
+            .into_iter()
+            .map(|i| i.module)
+            .collect();
+        assert_eq!(
+            modules,
+            vec!["ujson", "json", "requests", "yaml", "uvicorn"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_skips_imports_under_constant_false_guards() -> Result<(), ParseError> {
+        let code = r#"
+if False:
+    import foo
+if 0:
+    import bar
+else:
+    import baz
+if None:
+    import qux
+if DEBUG:
+    import debugpy
+"#;
+        let modules: Vec<String> = extract_dependencies(code)?
+            .into_iter()
+            .map(|i| i.module)
+            .collect();
+        assert_eq!(modules, vec!["baz", "debugpy"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_finds_dynamic_imports() -> Result<(), ParseError> {
+        let code = r#"
+import importlib
+from importlib import import_module
+
+def load(name):
+    backend = importlib.import_module("redis.client")
+    tasks = __import__("celery")
+    views = import_module(".views", package=__name__)
+    plugin = importlib.import_module(name)
+    if False:
+        importlib.import_module("disabled")
+"#;
+        let imports = extract_dependencies(code)?;
+        let dynamic: Vec<String> = imports
+            .iter()
+            .filter(|i| i.dynamic)
+            .map(|i| format!("{}: {i}", i.line))
+            .collect();
+        assert_eq!(
+            dynamic,
+            vec![
+                "6: import_module(\"redis.client\")",
+                "7: import_module(\"celery\")",
+                "8: import_module(\".views\")",
+            ]
+        );
+        assert!(imports
+            .iter()
+            .find(|i| i.module == "views")
+            .unwrap()
+            .is_relative());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tags_type_checking_imports() -> Result<(), ParseError> {
+        let code = r#"
+import typing
+from typing import TYPE_CHECKING
+
+if TYPE_CHECKING:
+    from mypy_boto3_s3 import S3Client
+else:
+    S3Client = object
+if typing.TYPE_CHECKING:
+    import pandas
+import boto3
+"#;
+        let imports = extract_dependencies(code)?;
+        let type_checking: Vec<&str> = imports
+            .iter()
+            .filter(|i| i.type_checking)
+            .map(|i| i.module.as_str())
+            .collect();
+        assert_eq!(type_checking, vec!["mypy_boto3_s3", "pandas"]);
+        assert!(
+            !imports
+                .iter()
+                .find(|i| i.module == "boto3")
+                .unwrap()
+                .type_checking
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_flags_imports_falling_back_on_import_error() -> Result<(), ParseError> {
+        let code = r#"
+try:
+    import lxml
+except ImportError:
+    lxml = None
+try:
+    import ujson as json
+except (AttributeError, ModuleNotFoundError):
+    import json
+else:
+    import orjson
+try:
+    import yaml
+    try:
+        from yaml import CLoader
+    except Exception:
+        CLoader = None
+except ImportError:
+    yaml = None
+try:
+    import simplejson
+except:
+    simplejson = None
+try:
+    import requests
+except ValueError:
+    pass
+"#;
+        let imports = extract_dependencies(code)?;
+        let optional: Vec<String> = imports
+            .iter()
+            .filter(|i| i.optional)
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            optional,
+            [
+                "import lxml",
+                "import ujson as json",
+                "import yaml",
+                "from yaml import CLoader"
+            ]
+        );
+        // The fallbacks and the else branch run when the import worked
+        assert!(imports.iter().any(|i| i.module == "json" && !i.optional));
+        assert!(imports.iter().any(|i| i.module == "orjson" && !i.optional));
+        Ok(())
+    }
+
+    #[test]
+    fn test_marks_imports_guarded_by_version_checks() -> Result<(), ParseError> {
+        let code = r#"
+import sys
+from sys import version_info
+
+if sys.version_info < (3, 8):
+    import importlib_metadata
+else:
+    import importlib.metadata as importlib_metadata
+if version_info >= (3, 11):
+    import tomllib
+elif sys.version_info > (3, 6):
+    if sys.version_info <= (3, 9):
+        import tomli
+if sys.version_info[:2] < (3, 8):
+    import typing_extensions
+import requests
+"#;
+        let imports = extract_dependencies(code)?;
+        let marker = |module: &str| {
+            imports
+                .iter()
+                .find(|i| i.module == module)
+                .unwrap()
+                .marker
+                .clone()
+        };
+        assert_eq!(
+            marker("importlib_metadata").as_deref(),
+            Some("python_version < '3.8'")
+        );
+        assert_eq!(
+            marker("importlib.metadata").as_deref(),
+            Some("python_version >= '3.8'")
+        );
+        assert_eq!(
+            marker("tomllib").as_deref(),
+            Some("python_version >= '3.11'")
+        );
+        assert_eq!(
+            marker("tomli").as_deref(),
+            Some("python_version < '3.11' and python_version >= '3.6' and python_version < '3.9'")
+        );
+        // Slices compare differently, they're left alone
+        assert_eq!(marker("typing_extensions"), None);
+        assert_eq!(marker("requests"), None);
         Ok(())
     }
 }
@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Apply `f` to every item on at most `workers` threads, each taking the next
+/// item as soon as it's done with the previous one. Results keep the order of
+/// `items`
+pub fn map_bounded<T, R, F>(items: &[T], workers: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+    let workers = workers.clamp(1, items.len().max(1));
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_never_runs_more_than_workers_at_once() {
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..40).collect();
+        let results = map_bounded(&items, 4, |i| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most_running.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            running.fetch_sub(1, Ordering::SeqCst);
+            i * 2
+        });
+        assert_eq!(results, (0..40).map(|i| i * 2).collect::<Vec<_>>());
+        assert_eq!(most_running.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_fewer_items_than_workers() {
+        assert_eq!(map_bounded(&[1, 2], 8, |i| i + 1), vec![2, 3]);
+        assert!(map_bounded(&[] as &[u8], 8, |i| *i).is_empty());
+        // Zero workers still gets the work done
+        assert_eq!(map_bounded(&[1], 0, |i| *i), vec![1]);
+    }
+}
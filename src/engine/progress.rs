@@ -0,0 +1,65 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A `label done/total` counter redrawn in place on stderr as work finishes,
+/// drawing nothing when disabled
+pub struct Progress {
+    label: &'static str,
+    total: usize,
+    done: AtomicUsize,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(label: &'static str, total: usize, enabled: bool) -> Self {
+        let progress = Progress {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+            enabled,
+        };
+        progress.draw(0);
+        progress
+    }
+
+    /// Count one more item as done
+    pub fn inc(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.draw(done);
+    }
+
+    /// Clear the line so the logs after it start on a clean one
+    pub fn finish(&self) {
+        if self.enabled {
+            eprint!("\r\x1b[2K");
+        }
+    }
+
+    fn line(&self, done: usize) -> String {
+        format!("{} {done}/{}", self.label, self.total)
+    }
+
+    fn draw(&self, done: usize) {
+        if self.enabled {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K{}", self.line(done));
+            let _ = stderr.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_done_items() {
+        let progress = Progress::new("Resolving", 3, false);
+        progress.inc();
+        progress.inc();
+        assert_eq!(
+            progress.line(progress.done.load(Ordering::Relaxed)),
+            "Resolving 2/3"
+        );
+    }
+}
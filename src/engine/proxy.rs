@@ -0,0 +1,87 @@
+use std::env;
+
+/// The proxy url set in the environment, `HTTPS_PROXY`, then `HTTP_PROXY`,
+/// then `ALL_PROXY`, in upper or lower case
+pub fn proxy_from_env() -> Option<String> {
+    ["HTTPS_PROXY", "HTTP_PROXY", "ALL_PROXY"]
+        .iter()
+        .flat_map(|name| [name.to_string(), name.to_lowercase()])
+        .filter_map(|name| env::var(name).ok())
+        .find(|url| !url.trim().is_empty())
+}
+
+/// The hosts of `NO_PROXY` that are reached directly rather than through
+/// the proxy, each matching itself and its subdomains
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NoProxy {
+    hosts: Vec<String>,
+    all: bool,
+}
+
+impl NoProxy {
+    /// Read `NO_PROXY`, or `no_proxy`. Empty when neither is set
+    pub fn from_env() -> Self {
+        match env::var("NO_PROXY").or_else(|_| env::var("no_proxy")) {
+            Ok(value) => NoProxy::parse(&value),
+            Err(_) => NoProxy::default(),
+        }
+    }
+
+    /// Comma separated hosts, `corp.internal`, `.corp.internal` or
+    /// `nexus.corp:8443`, with `*` for every host
+    pub fn parse(value: &str) -> Self {
+        let mut no_proxy = NoProxy::default();
+        for entry in value.split([',', ' ']).map(str::trim) {
+            match entry.trim_start_matches('*').trim_start_matches('.') {
+                "" if entry == "*" => no_proxy.all = true,
+                "" => {}
+                host => no_proxy.hosts.push(host.to_lowercase()),
+            }
+        }
+        no_proxy
+    }
+
+    /// Whether a request to `url` skips the proxy
+    pub fn bypasses(&self, url: &str) -> bool {
+        if self.all {
+            return true;
+        }
+        let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let authority = rest[..rest.find('/').unwrap_or(rest.len())].to_lowercase();
+        let host = authority
+            .rsplit_once(':')
+            .map_or(authority.as_str(), |(host, _)| host);
+        self.hosts.iter().any(|entry| {
+            // An entry with a port only matches that port
+            let target = match entry.contains(':') {
+                true => authority.as_str(),
+                false => host,
+            };
+            target == entry
+                || target
+                    .strip_suffix(entry.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_proxy() {
+        let no_proxy = NoProxy::parse("localhost, .corp.internal,nexus.corp:8443 *.svc");
+        assert!(no_proxy.bypasses("http://localhost:8080/simple"));
+        assert!(no_proxy.bypasses("https://corp.internal/simple"));
+        assert!(no_proxy.bypasses("https://pypi.corp.internal/simple"));
+        assert!(!no_proxy.bypasses("https://notcorp.internal/simple"));
+        assert!(no_proxy.bypasses("https://nexus.corp:8443/simple"));
+        assert!(!no_proxy.bypasses("https://nexus.corp/simple"));
+        assert!(no_proxy.bypasses("https://index.svc/simple"));
+        assert!(!no_proxy.bypasses("https://pypi.org/simple"));
+
+        assert!(NoProxy::parse("*").bypasses("https://pypi.org/simple"));
+        assert!(!NoProxy::parse("").bypasses("https://pypi.org/simple"));
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits the rate of requests across every thread that shares it by
+/// handing out evenly spaced send slots, at most `rps` per second
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(rps: f64) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / rps),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block until the caller is allowed to send another request
+    pub fn acquire(&self) {
+        let wait = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + self.interval;
+            slot - now
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_spaces_out_acquires_across_threads() {
+        let limiter = Arc::new(RateLimiter::new(20.0));
+        let start = Instant::now();
+        let handles: Vec<_> = (0..5)
+            .map(|_| {
+                let limiter = limiter.clone();
+                thread::spawn(move || limiter.acquire())
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        // First slot is free, the other four wait 50ms each
+        assert!(start.elapsed() >= Duration::from_millis(195));
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+/// Latest stable versions of popular packages, bundled in the binary so new
+/// dependencies still get a reasonable pin when no index can be reached.
+/// Refresh these from the PyPI JSON API when cutting a release
+pub fn get_snapshot_versions() -> HashMap<&'static str, &'static str> {
+    HashMap::from([
+        ("aiohttp", "3.11.14"),
+        ("attrs", "25.3.0"),
+        ("beautifulsoup4", "4.13.3"),
+        ("black", "25.1.0"),
+        ("celery", "5.4.0"),
+        ("click", "8.1.8"),
+        ("coverage", "7.8.0"),
+        ("cryptography", "44.0.2"),
+        ("django", "5.1.7"),
+        ("docker", "7.1.0"),
+        ("fastapi", "0.115.12"),
+        ("flask", "3.1.0"),
+        ("grpcio", "1.71.0"),
+        ("gunicorn", "23.0.0"),
+        ("httpx", "0.28.1"),
+        ("jinja2", "3.1.6"),
+        ("jsonschema", "4.23.0"),
+        ("lxml", "5.3.1"),
+        ("markdown", "3.7"),
+        ("marshmallow", "3.26.1"),
+        ("matplotlib", "3.10.1"),
+        ("mypy", "1.15.0"),
+        ("networkx", "3.4.2"),
+        ("numpy", "2.2.4"),
+        ("openpyxl", "3.1.5"),
+        ("orjson", "3.10.16"),
+        ("packaging", "24.2"),
+        ("pandas", "2.2.3"),
+        ("paramiko", "3.5.1"),
+        ("pillow", "11.1.0"),
+        ("psutil", "7.0.0"),
+        ("psycopg2-binary", "2.9.10"),
+        ("pydantic", "2.10.6"),
+        ("pyjwt", "2.10.1"),
+        ("pytest", "8.3.5"),
+        ("python-dotenv", "1.1.0"),
+        ("pyyaml", "6.0.2"),
+        ("redis", "5.2.1"),
+        ("requests", "2.32.3"),
+        ("rich", "13.9.4"),
+        ("scikit-learn", "1.6.1"),
+        ("scipy", "1.15.2"),
+        ("setuptools", "78.1.0"),
+        ("six", "1.17.0"),
+        ("sphinx", "8.2.3"),
+        ("sqlalchemy", "2.0.40"),
+        ("sympy", "1.13.3"),
+        ("tenacity", "9.0.0"),
+        ("tomli", "2.2.1"),
+        ("tqdm", "4.67.1"),
+        ("typer", "0.15.2"),
+        ("urllib3", "2.3.0"),
+        ("uvicorn", "0.34.0"),
+        ("werkzeug", "3.1.3"),
+    ])
+}
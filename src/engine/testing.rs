@@ -0,0 +1,94 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use tempfile::TempDir;
+
+/// Requests seen by a mock index as (path, time received)
+pub type RequestLog = Arc<Mutex<Vec<(String, Instant)>>>;
+
+/// Start a tiny HTTP server on localhost that answers every request with
+/// whatever `handler` returns for (path, request number). Returns the base url
+/// of the server and a log of the requests it received.
+pub fn mock_index<F>(handler: F) -> (String, RequestLog)
+where
+    F: Fn(&str, usize) -> String + Send + 'static,
+{
+    mock_index_with_headers(move |path, n, _| handler(path, n))
+}
+
+/// Like [`mock_index`], also handing the request's header lines to `handler`
+pub fn mock_index_with_headers<F>(handler: F) -> (String, RequestLog)
+where
+    F: Fn(&str, usize, &[String]) -> String + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let log: RequestLog = Arc::new(Mutex::new(Vec::new()));
+    let server_log = log.clone();
+    thread::spawn(move || {
+        for (n, stream) in listener.incoming().enumerate() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            let mut headers = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok() && line != "\r\n" && !line.is_empty() {
+                headers.push(line.trim_end().to_string());
+                line.clear();
+            }
+            let path = request_line
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or("/")
+                .to_string();
+            server_log
+                .lock()
+                .unwrap()
+                .push((path.clone(), Instant::now()));
+            let _ = stream.write_all(handler(&path, n, &headers).as_bytes());
+        }
+    });
+    (format!("http://{addr}"), log)
+}
+
+/// Build a raw HTTP/1.1 response
+pub fn http_response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {status}\r\n");
+    for (name, value) in headers {
+        response += &format!("{name}: {value}\r\n");
+    }
+    response += &format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    response
+}
+
+/// A site-packages directory with a dist-info for each (name, version)
+pub fn site_packages(packages: &[(&str, &str)]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    for (name, version) in packages {
+        let dist_info = dir
+            .path()
+            .join(format!("{}-{version}.dist-info", name.replace('-', "_")));
+        fs::create_dir(&dist_info).unwrap();
+        fs::write(
+            dist_info.join("METADATA"),
+            format!(
+                "Metadata-Version: 2.1\nName: {name}\nVersion: {version}\n\nName: not-a-header\n"
+            ),
+        )
+        .unwrap();
+    }
+    dir
+}
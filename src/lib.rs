@@ -0,0 +1,36 @@
+//! Detect the third party packages a Python project imports and add the
+//! ones missing from its pyproject.toml.
+//!
+//! The `pydepsync` binary is a thin layer over this crate. To detect missing
+//! dependencies from another tool, read the project with [`pyproject::read`]
+//! and run a [`DetectEngine`] over its code:
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//!
+//! use pydepsync::{pyproject, DetectEngine, EngineOptions};
+//!
+//! let project = pyproject::read(&PathBuf::from("pyproject.toml")).unwrap();
+//! let options = EngineOptions {
+//!     offline: true,
+//!     ..EngineOptions::default()
+//! };
+//! let engine = DetectEngine::new(project, options).unwrap();
+//! for missing in engine.detect(PathBuf::from(".")).unwrap().missing {
+//!     println!("{} imported at {:?}", missing.dependency, missing.sources);
+//! }
+//! ```
+
+pub mod cli;
+pub mod config;
+pub mod dependency;
+pub mod engine;
+mod poetry;
+pub mod pyproject;
+pub mod requirements;
+pub mod sections;
+mod version;
+
+pub use dependency::Dependency;
+pub use engine::{DetectEngine, DetectEngineError, DetectedDependency, Detection, EngineOptions};
+pub use pyproject::PyProject;
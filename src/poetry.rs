@@ -0,0 +1,218 @@
+use regex::Regex;
+use toml_edit::{Array, InlineTable, Item, Value};
+
+use crate::dependency::Dependency;
+
+/// The dependency a `[tool.poetry.dependencies]` entry declares, either a
+/// constraint string or a table with `version`, `extras` and `markers`.
+/// None for the `python` entry and anything that isn't a package
+pub fn dependency(name: &str, item: &Item) -> Option<Dependency> {
+    if name == "python" {
+        return None;
+    }
+    let (constraint, extras, markers) = match item.as_table_like() {
+        Some(table) => (
+            table.get("version").and_then(Item::as_str),
+            table
+                .get("extras")
+                .and_then(Item::as_array)
+                .map(|a| a.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+                .unwrap_or_default(),
+            table.get("markers").and_then(Item::as_str),
+        ),
+        None => (Some(item.as_str()?), Vec::new(), None),
+    };
+    let mut requirement = name.to_string();
+    if !extras.is_empty() {
+        requirement += &format!("[{}]", extras.join(","));
+    }
+    let specs: Vec<String> = specifiers(constraint.unwrap_or("*"))
+        .iter()
+        .map(|(op, version)| format!("{op}{version}"))
+        .collect();
+    requirement += &specs.join(",");
+    if let Some(markers) = markers {
+        requirement += &format!("; {markers}");
+    }
+    Dependency::parse(&requirement)
+}
+
+/// A Poetry constraint as PEP 440 specifiers, `^1.2` is `>=1.2,<2.0`, `~1.2`
+/// is `>=1.2,<1.3` and a bare version is an exact one. Alternatives joined
+/// with `||` can't be expressed and come back empty, like `*`
+pub fn specifiers(constraint: &str) -> Vec<(String, String)> {
+    if constraint.contains("||") {
+        return Vec::new();
+    }
+    // Parts are separated by commas or spaces, `>= 1.2 <2` is `>=1.2,<2`
+    let part_re = Regex::new(r"([~^=<>!]*)\s*([^\s,~^=<>!]+)").unwrap();
+    let mut specs = Vec::new();
+    for part in part_re.captures_iter(constraint) {
+        let (op, version) = (&part[1], &part[2]);
+        if version == "*" {
+            continue;
+        }
+        let release = release(version);
+        match op {
+            "^" => {
+                // Bump the first non-zero component, or the last one when all are zero
+                let bumped = release
+                    .iter()
+                    .position(|&c| c != 0)
+                    .unwrap_or(release.len().saturating_sub(1));
+                specs.push((">=".to_string(), version.to_string()));
+                specs.push(("<".to_string(), bump(&release, bumped)));
+            }
+            "~" => {
+                specs.push((">=".to_string(), version.to_string()));
+                specs.push((
+                    "<".to_string(),
+                    bump(&release, release.len().clamp(1, 2) - 1),
+                ));
+            }
+            "" | "=" => specs.push(("==".to_string(), version.to_string())),
+            _ => specs.push((op.to_string(), version.to_string())),
+        }
+    }
+    specs
+}
+
+/// The Poetry constraint for a dependency's version specs, `~=1.2.3` becomes
+/// `~1.2.3` and `~=1.2` becomes `^1.2`, which mean the same thing
+pub fn constraint(dep: &Dependency) -> String {
+    let specs = dep.version_specs();
+    if let [(op, version)] = specs {
+        let release = release(version);
+        match (op.as_str(), release.as_slice()) {
+            ("==", _) => return version.to_string(),
+            ("~=", [_, _, _]) => return format!("~{version}"),
+            ("~=", [major, _]) if *major > 0 => return format!("^{version}"),
+            // `^0.2` stops at 0.3, spell the range out instead
+            ("~=", [_, _, ..]) => {
+                return format!(">={version},<{}", bump(&release, release.len() - 2))
+            }
+            _ => {}
+        }
+    }
+    match specs.is_empty() {
+        true => "*".to_string(),
+        false => specs
+            .iter()
+            .map(|(op, version)| format!("{op}{version}"))
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+/// The value of a `[tool.poetry.dependencies]` entry for a dependency, a table
+/// when it has extras or markers and a constraint string otherwise
+pub fn entry(dep: &Dependency) -> Value {
+    let constraint = constraint(dep);
+    if dep.extras().is_empty() && dep.markers().is_none() {
+        return Value::from(constraint);
+    }
+    let mut table = InlineTable::new();
+    table.insert("version", Value::from(constraint));
+    if !dep.extras().is_empty() {
+        let mut extras: Vec<&String> = dep.extras().iter().collect();
+        extras.sort();
+        table.insert(
+            "extras",
+            Value::Array(extras.into_iter().collect::<Array>()),
+        );
+    }
+    if let Some(markers) = dep.markers() {
+        table.insert("markers", Value::from(markers));
+    }
+    Value::InlineTable(table)
+}
+
+// The numeric release components of a version, `1.2.0rc1` is [1, 2, 0]
+fn release(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map_while(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+        .collect()
+}
+
+// The release with component `i` incremented and the ones after it zeroed
+fn bump(release: &[u64], i: usize) -> String {
+    let mut bumped: Vec<u64> = release.to_vec();
+    if bumped.len() <= i {
+        bumped.resize(i + 1, 0);
+    }
+    bumped[i] += 1;
+    for c in &mut bumped[i + 1..] {
+        *c = 0;
+    }
+    bumped
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(constraint: &str) -> Vec<String> {
+        specifiers(constraint)
+            .into_iter()
+            .map(|(op, version)| format!("{op}{version}"))
+            .collect()
+    }
+
+    #[test]
+    fn test_specifiers() {
+        assert_eq!(specs("^1.2.3"), vec![">=1.2.3", "<2.0.0"]);
+        assert_eq!(specs("^0.2.3"), vec![">=0.2.3", "<0.3.0"]);
+        assert_eq!(specs("^0.0"), vec![">=0.0", "<0.1"]);
+        assert_eq!(specs("~1.2.3"), vec![">=1.2.3", "<1.3.0"]);
+        assert_eq!(specs("~1"), vec![">=1", "<2"]);
+        assert_eq!(specs("1.4"), vec!["==1.4"]);
+        assert_eq!(specs(">=2.0, <3.0"), vec![">=2.0", "<3.0"]);
+        assert_eq!(specs(">= 2.0 <3.0"), vec![">=2.0", "<3.0"]);
+        assert!(specs("*").is_empty());
+        assert!(specs("^1.0 || ^2.0").is_empty());
+    }
+
+    #[test]
+    fn test_constraint() {
+        let constraint = |dep: &str| constraint(&Dependency::parse(dep).unwrap());
+        assert_eq!(constraint("requests~=2.32.3"), "~2.32.3");
+        assert_eq!(constraint("requests~=2.32"), "^2.32");
+        assert_eq!(constraint("attrs~=0.4"), ">=0.4,<1.0");
+        assert_eq!(constraint("django==4.2.7"), "4.2.7");
+        assert_eq!(constraint("django>=4.2,<5"), ">=4.2,<5");
+        assert_eq!(constraint("django"), "*");
+    }
+
+    #[test]
+    fn test_entries_round_trip() {
+        for (requirement, entry_repr) in [
+            ("requests~=2.32.3", "\"~2.32.3\""),
+            (
+                "django[argon2,bcrypt]>=4.2; python_version >= '3.10'",
+                "{ version = \">=4.2\", extras = [\"argon2\", \"bcrypt\"], markers = \"python_version >= '3.10'\" }",
+            ),
+        ] {
+            let dep = Dependency::parse(requirement).unwrap();
+            let entry = entry(&dep);
+            assert_eq!(entry.to_string().trim(), entry_repr);
+            let read = dependency(&dep.name(), &Item::Value(entry)).unwrap();
+            assert_eq!(read.extras(), dep.extras());
+            assert_eq!(read.markers(), dep.markers());
+        }
+        assert_eq!(dependency("python", &Item::Value("^3.9".into())), None);
+        assert_eq!(
+            dependency("black", &Item::Value("^24.1".into()))
+                .unwrap()
+                .to_dependency_repr(),
+            "black>=24.1,<25.0"
+        );
+    }
+}
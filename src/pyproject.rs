@@ -1,22 +1,320 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::fs::{self};
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use log::{debug, info};
+use log::{debug, info, warn};
+use serde::Deserialize;
 use taplo::formatter::{format, Options};
-use toml_edit::{value, Array, DocumentMut, Item};
+use tempfile::NamedTempFile;
+use toml_edit::{table, value, Array, Decor, DocumentMut, Item, Table, TableLike, Value};
 
-use crate::dependency::Dependency;
+use crate::dependency::{normalize_name, Dependency, SpecTemplate, Transforms};
+use crate::poetry;
+use crate::sections::Section;
+use crate::version::specifiers_compatible;
 
+/// Options controlling how new dependencies are written
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    pub transforms: Transforms,
+    pub spec_template: Option<SpecTemplate>,
+    /// Keep a `# last synced by pydepsync` comment above `project.dependencies`
+    pub sync_comment: bool,
+    pub sort_order: SortOrder,
+    /// Format the whole file with taplo, rather than only touching the entries added
+    pub reformat: bool,
+}
+
+/// How the dependency arrays pydepsync writes to are ordered
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    /// Alphabetical, ignoring case
+    #[serde(alias = "alphabetical")]
+    AlphaCi,
+    /// Alphabetical, uppercase before lowercase
+    AlphaCs,
+    /// Keep the existing order and append new dependencies, themselves sorted
+    #[default]
+    #[serde(alias = "append", alias = "off")]
+    None,
+}
+
+impl SortOrder {
+    fn sort(&self, arr: &mut Array) {
+        let key: fn(&Value) -> String = match self {
+            SortOrder::AlphaCi => |v| sort_key(v).to_lowercase(),
+            SortOrder::AlphaCs => sort_key,
+            SortOrder::None => return,
+        };
+        if has_comments(arr) {
+            sort_keeping_comments(arr, key);
+        } else {
+            arr.sort_by_key(key);
+        }
+    }
+}
+
+// Add an entry at the end of an array. A comment on the last entry's line is
+// in the array's trailing decor, which would put it after the new entry
+fn append(arr: &mut Array, dep: String) {
+    let trailing = arr.trailing().as_str().unwrap_or("").to_string();
+    match trailing.split_once('\n') {
+        Some((line, rest)) if line.contains('#') => {
+            let mut dep = Value::from(dep);
+            dep.decor_mut().set_prefix(format!("{line}\n    "));
+            arr.push_formatted(dep);
+            arr.set_trailing(format!("\n{rest}"));
+        }
+        _ => {
+            // Lay the new entry out like the last one, so a multiline array
+            // stays one entry per line
+            let mut dep = Value::from(dep);
+            let len = arr.len();
+            match arr.get_mut(len.wrapping_sub(1)) {
+                Some(last) => {
+                    let prefix = last.decor().prefix().and_then(|p| p.as_str());
+                    let prefix = match prefix {
+                        Some(p) if p.contains('\n') => p.to_string(),
+                        _ => " ".to_string(),
+                    };
+                    // Whatever closes the last entry, like the newline before
+                    // `]` without a trailing comma, moves to the new last entry
+                    let suffix = last.decor().suffix().and_then(|s| s.as_str());
+                    let suffix = suffix.unwrap_or("").to_string();
+                    last.decor_mut().set_suffix("");
+                    dep.decor_mut().set_prefix(prefix);
+                    dep.decor_mut().set_suffix(suffix);
+                }
+                None if trailing.contains('\n') => {
+                    dep.decor_mut().set_prefix("\n    ");
+                    arr.set_trailing_comma(true);
+                }
+                None => {}
+            }
+            arr.push_formatted(dep);
+        }
+    }
+}
+
+fn sort_key(v: &Value) -> String {
+    v.as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| v.to_string())
+}
+
+fn has_comments(arr: &Array) -> bool {
+    let decor = |v: &Value| {
+        [v.decor().prefix(), v.decor().suffix()]
+            .into_iter()
+            .flatten()
+            .any(|raw| raw.as_str().is_some_and(|s| s.contains('#')))
+    };
+    arr.iter().any(decor) || arr.trailing().as_str().is_some_and(|s| s.contains('#'))
+}
+
+// A comment at the end of an entry's line is parsed into the decor of the
+// next entry, or the array's trailing decor for the last one. Sort the
+// entries with their comments so they stay on the same line
+fn sort_keeping_comments(arr: &mut Array, key: fn(&Value) -> String) {
+    // The rest of the line before the first newline, and everything after it
+    fn split_line(raw: &str) -> (String, String) {
+        match raw.split_once('\n') {
+            Some((line, rest)) => (line.to_string(), rest.to_string()),
+            None => (String::new(), raw.to_string()),
+        }
+    }
+    let prefix = |v: &Value| {
+        v.decor()
+            .prefix()
+            .and_then(|p| p.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+
+    let mut opening = String::new();
+    // (entry, the comment on its line, the lines above it)
+    let mut entries: Vec<(Value, String, String)> = Vec::new();
+    for (i, v) in arr.iter().enumerate() {
+        let (line, leading) = split_line(&prefix(v));
+        match entries.last_mut() {
+            Some(previous) => previous.1 = line,
+            None if i == 0 => opening = line,
+            None => {}
+        }
+        entries.push((v.clone(), String::new(), leading));
+    }
+    let (line, closing) = split_line(arr.trailing().as_str().unwrap_or(""));
+    if let Some(last) = entries.last_mut() {
+        last.1 = line;
+    }
+
+    entries.sort_by_key(|(v, _, _)| key(v));
+    let mut line = opening;
+    arr.clear();
+    for (mut v, comment, leading) in entries {
+        v.decor_mut().set_prefix(format!("{line}\n{leading}"));
+        arr.push_formatted(v);
+        line = comment;
+    }
+    arr.set_trailing(format!("{line}\n{closing}"));
+}
+
+impl WriteOptions {
+    /// The requirement string written for a new dependency
+    fn render(&self, dep: &Dependency) -> String {
+        let dep = self.transforms.apply(dep);
+        self.spec_template
+            .as_ref()
+            .and_then(|t| t.render(&dep))
+            .unwrap_or_else(|| dep.to_dependency_repr())
+    }
+
+    // Render new dependencies in a stable order, whatever the sort order
+    fn render_all(&self, deps: HashSet<Dependency>) -> Vec<String> {
+        let mut rendered: Vec<String> = deps.iter().map(|d| self.render(d)).collect();
+        rendered.sort_by_key(|d| d.to_lowercase());
+        rendered
+    }
+}
+
+/// Where a project declares its dependencies
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Layout {
+    /// PEP 621 `project.dependencies` arrays of requirement strings
+    #[default]
+    Pep621,
+    /// Poetry's `[tool.poetry.dependencies]` tables of `name = "constraint"`
+    Poetry,
+    /// PEP 621, with development dependencies in `[tool.pdm.dev-dependencies]`
+    Pdm,
+    /// PEP 621, with the dependencies of environments in `[tool.hatch.envs.*]`
+    Hatch,
+}
+
+/// A project's pyproject.toml, the dependencies it declares along with the
+/// document itself so writing back keeps everything else as it was
 #[derive(Debug, Clone)]
 pub struct PyProject {
     deps: HashSet<Dependency>,
     optional_deps: HashSet<Dependency>,
+    /// Dependencies declared in groups and extras, with where they're declared
+    section_deps: Vec<(Section, Dependency)>,
     toml_document: DocumentMut,
+    conflicts: Vec<SpecConflict>,
+    requires_python: Option<String>,
+    layout: Layout,
+}
+
+/// The same package declared in two sections with version specs that no
+/// single version can satisfy, e.g. `requests>=2` and `requests<2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecConflict {
+    pub first: (String, Dependency),
+    pub second: (String, Dependency),
+}
+
+impl Display for SpecConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in {} conflicts with {} in {}",
+            self.first.1, self.first.0, self.second.1, self.second.0
+        )
+    }
 }
 
 impl PyProject {
+    /// A project with only runtime dependencies and no pyproject.toml behind
+    /// it, e.g. from a requirements.txt
+    pub fn from_deps(deps: HashSet<Dependency>) -> Self {
+        PyProject {
+            deps,
+            optional_deps: HashSet::new(),
+            section_deps: Vec::new(),
+            toml_document: DocumentMut::new(),
+            conflicts: Vec::new(),
+            requires_python: None,
+            layout: Layout::Pep621,
+        }
+    }
+
+    pub fn deps(&self) -> &HashSet<Dependency> {
+        &self.deps
+    }
+
+    pub fn conflicts(&self) -> &[SpecConflict] {
+        &self.conflicts
+    }
+
+    /// The `project.requires-python` constraint, e.g. `>=3.9`
+    pub fn requires_python(&self) -> Option<&str> {
+        self.requires_python.as_deref()
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Dependencies declared in dependency groups and extras
+    pub fn section_deps(&self) -> &[(Section, Dependency)] {
+        &self.section_deps
+    }
+
+    /// Move a dependency declared in a group or extra to the runtime
+    /// dependencies, keeping its version spec
+    pub fn promote(&mut self, section: &Section, dep: &Dependency) {
+        if let Some(arr) = section_array(&mut self.toml_document, section) {
+            arr.retain(|v| v.as_str().and_then(Dependency::parse).as_ref() != Some(dep));
+        }
+        let Some(project) = self
+            .toml_document
+            .get_mut("project")
+            .and_then(Item::as_table_like_mut)
+        else {
+            return;
+        };
+        if self.deps.iter().any(|d| dep.satisfied_by(d)) {
+            // Declared in several places, the runtime entry already covers it
+        } else if let Some(arr) = project
+            .entry("dependencies")
+            .or_insert(value(Array::new()))
+            .as_array_mut()
+        {
+            arr.push(dep.to_dependency_repr());
+        }
+        self.section_deps
+            .retain(|(s, d)| !(s == section && d == dep));
+        self.deps.insert(dep.clone());
+    }
+
+    /// Drop a runtime dependency, e.g. one nothing imports anymore
+    pub fn remove(&mut self, dep: &Dependency) {
+        match self.layout {
+            Layout::Poetry => {
+                if let Some(table) = poetry_table(&mut self.toml_document, &Section::Runtime) {
+                    let keys: Vec<String> = table
+                        .iter()
+                        .filter(|(name, _)| normalize_name(name) == dep.normalized_name())
+                        .map(|(name, _)| name.to_string())
+                        .collect();
+                    for key in keys {
+                        table.remove(&key);
+                    }
+                }
+            }
+            _ => {
+                if let Some(arr) = section_array(&mut self.toml_document, &Section::Runtime) {
+                    arr.retain(|v| v.as_str().and_then(Dependency::parse).as_ref() != Some(dep));
+                }
+            }
+        }
+        self.deps.remove(dep);
+    }
+
     pub fn all_deps(&self) -> HashSet<Dependency> {
         let mut all_deps = HashSet::new();
         for dep in self.deps.clone() {
@@ -29,9 +327,20 @@ impl PyProject {
     }
 }
 
+/// Walk up from `start` to the closest pyproject.toml, the same way git
+/// finds the root of a repository
+pub fn discover(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join("pyproject.toml"))
+        .find(|path| path.is_file())
+}
+
 pub fn read(path: &PathBuf) -> Result<PyProject, io::Error> {
     let content = fs::read_to_string(path)?;
-    let doc = content.parse::<DocumentMut>().unwrap();
+    let doc = content
+        .parse::<DocumentMut>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
     // get existing deps
     let mut existing_deps = Array::new();
@@ -41,17 +350,25 @@ pub fn read(path: &PathBuf) -> Result<PyProject, io::Error> {
         }
     }
 
+    // Every declared dependency along with the section it came from
+    let mut declared: Vec<(String, Dependency)> = Vec::new();
+
     // Access the "dependency-groups" table
     let mut optional_dependencies: HashSet<Dependency> = HashSet::new();
+    let mut section_deps: Vec<(Section, Dependency)> = Vec::new();
     if let Some(Item::Table(table)) = doc.get("dependency-groups") {
         // Iterate through each group in dependency-groups
-        for (_group_name, group_value) in table.iter() {
+        for (group_name, group_value) in table.iter() {
             if let Item::Value(value) = group_value {
                 // If the value is an array, process each dependency
                 if let Some(array) = value.as_array() {
                     for dep in array {
                         if let Some(dep_str) = dep.as_str() {
-                            optional_dependencies.insert(Dependency::parse(dep_str).unwrap());
+                            let dep = Dependency::parse(dep_str).unwrap();
+                            declared.push((format!("dependency-groups.{group_name}"), dep.clone()));
+                            section_deps
+                                .push((Section::Group(group_name.to_string()), dep.clone()));
+                            optional_dependencies.insert(dep);
                         }
                     }
                 }
@@ -62,12 +379,19 @@ pub fn read(path: &PathBuf) -> Result<PyProject, io::Error> {
     // Parse project.optional-dependencies
     if let Some(Item::Table(project_table)) = doc.get("project") {
         if let Some(Item::Table(opt_deps_table)) = project_table.get("optional-dependencies") {
-            for (_group_name, group_value) in opt_deps_table.iter() {
+            for (group_name, group_value) in opt_deps_table.iter() {
                 if let Item::Value(value) = group_value {
                     if let Some(array) = value.as_array() {
                         for dep in array {
                             if let Some(dep_str) = dep.as_str() {
-                                optional_dependencies.insert(Dependency::parse(dep_str).unwrap());
+                                let dep = Dependency::parse(dep_str).unwrap();
+                                declared.push((
+                                    format!("project.optional-dependencies.{group_name}"),
+                                    dep.clone(),
+                                ));
+                                section_deps
+                                    .push((Section::Extra(group_name.to_string()), dep.clone()));
+                                optional_dependencies.insert(dep);
                             }
                         }
                     }
@@ -76,10 +400,108 @@ pub fn read(path: &PathBuf) -> Result<PyProject, io::Error> {
         }
     }
 
-    let existing_deps: HashSet<Dependency> = existing_deps
+    let mut existing_deps: HashSet<Dependency> = existing_deps
         .iter()
         .map(|v| Dependency::parse(v.as_str().unwrap()).unwrap())
         .collect();
+
+    // Poetry projects that haven't moved to PEP 621 yet
+    let tool = |name: &str| doc.get("tool").and_then(|t| t.get(name));
+    let poetry = tool("poetry");
+    let poetry_deps = poetry
+        .and_then(|p| p.get("dependencies"))
+        .and_then(Item::as_table_like);
+    let project_deps = doc.get("project").and_then(|p| p.get("dependencies"));
+    let layout = match (poetry_deps, project_deps) {
+        (Some(_), None) => Layout::Poetry,
+        _ if tool("pdm").is_some() => Layout::Pdm,
+        _ if tool("hatch").is_some() => Layout::Hatch,
+        _ => Layout::Pep621,
+    };
+    // The tool's own sections, declared dependencies that aren't runtime ones
+    let mut tool_sections: Vec<(String, &Item)> = Vec::new();
+    match layout {
+        Layout::Pep621 | Layout::Poetry => {}
+        Layout::Pdm => {
+            let groups = tool("pdm")
+                .and_then(|p| p.get("dev-dependencies"))
+                .and_then(Item::as_table_like);
+            for (group_name, item) in groups.into_iter().flat_map(|t| t.iter()) {
+                tool_sections.push((format!("tool.pdm.dev-dependencies.{group_name}"), item));
+            }
+        }
+        Layout::Hatch => {
+            let envs = tool("hatch")
+                .and_then(|h| h.get("envs"))
+                .and_then(Item::as_table_like);
+            for (env_name, env) in envs.into_iter().flat_map(|t| t.iter()) {
+                for key in ["dependencies", "extra-dependencies"] {
+                    if let Some(item) = env.get(key) {
+                        tool_sections.push((format!("tool.hatch.envs.{env_name}.{key}"), item));
+                    }
+                }
+            }
+        }
+    }
+    for (section, item) in tool_sections {
+        // Unlike the PEP 621 sections these can hold editable paths and urls,
+        // e.g. `-e file:///${PROJECT_ROOT}/packages/shared`, declaring the
+        // local package they point to
+        for dep in item
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+            .filter_map(|v| match v.starts_with('-') {
+                true => editable_dependency(v),
+                false => Dependency::parse(v),
+            })
+        {
+            declared.push((section.clone(), dep.clone()));
+            optional_dependencies.insert(dep);
+        }
+    }
+    // Packages uv gets from a path or the workspace rather than an index
+    let uv_sources = tool("uv")
+        .and_then(|uv| uv.get("sources"))
+        .and_then(Item::as_table_like);
+    for (name, _) in uv_sources.into_iter().flat_map(|t| t.iter()) {
+        if let Some(dep) = Dependency::parse(name) {
+            optional_dependencies.insert(dep);
+        }
+    }
+    if layout == Layout::Poetry {
+        existing_deps.extend(poetry_deps.into_iter().flat_map(poetry_table_deps));
+        // Groups, and the dev-dependencies table they replaced
+        let groups = poetry
+            .and_then(|p| p.get("group"))
+            .and_then(Item::as_table_like)
+            .into_iter()
+            .flat_map(|t| t.iter())
+            .filter_map(|(name, group)| Some((name, group.get("dependencies")?)))
+            .chain(poetry.and_then(|p| Some(("dev", p.get("dev-dependencies")?))));
+        for (group_name, table) in groups {
+            for dep in table
+                .as_table_like()
+                .into_iter()
+                .flat_map(poetry_table_deps)
+            {
+                declared.push((format!("tool.poetry.group.{group_name}"), dep.clone()));
+                optional_dependencies.insert(dep);
+            }
+        }
+    }
+    let runtime_section = match layout {
+        Layout::Poetry => "tool.poetry.dependencies",
+        _ => "project.dependencies",
+    };
+    for dep in &existing_deps {
+        declared.push((runtime_section.to_string(), dep.clone()));
+    }
+    let conflicts = find_conflicts(&declared);
+    for conflict in &conflicts {
+        warn!("Conflicting version specs: {conflict}");
+    }
     debug!(
         "Found existing deps: {}",
         existing_deps
@@ -88,36 +510,333 @@ pub fn read(path: &PathBuf) -> Result<PyProject, io::Error> {
             .collect::<Vec<_>>()
             .join(",")
     );
+    let requires_python = doc
+        .get("project")
+        .and_then(|p| p.get("requires-python"))
+        .and_then(|r| r.as_str())
+        .map(str::to_string)
+        .or_else(|| {
+            let python = poetry_deps?.get("python")?.as_str()?;
+            let specs: Vec<String> = poetry::specifiers(python)
+                .iter()
+                .map(|(op, version)| format!("{op}{version}"))
+                .collect();
+            Some(specs.join(","))
+        });
     Ok(PyProject {
         deps: existing_deps,
         optional_deps: optional_dependencies,
+        section_deps,
         toml_document: doc,
+        conflicts,
+        requires_python,
+        layout,
     })
 }
 
+fn poetry_table_deps(table: &dyn TableLike) -> impl Iterator<Item = Dependency> + '_ {
+    table
+        .iter()
+        .filter_map(|(name, item)| poetry::dependency(name, item))
+}
+
+// Find packages declared in more than one section with incompatible version specs
+fn find_conflicts(declared: &[(String, Dependency)]) -> Vec<SpecConflict> {
+    let mut by_name: HashMap<String, Vec<&(String, Dependency)>> = HashMap::new();
+    for entry in declared {
+        by_name
+            .entry(entry.1.normalized_name())
+            .or_default()
+            .push(entry);
+    }
+    let mut conflicts = Vec::new();
+    for entries in by_name.values() {
+        for (i, first) in entries.iter().enumerate() {
+            for second in &entries[i + 1..] {
+                if first.0 == second.0 {
+                    continue;
+                }
+                let specs: Vec<(String, String)> = [&first.1, &second.1]
+                    .iter()
+                    .flat_map(|d| d.version_specs().iter().cloned())
+                    .collect();
+                if !specifiers_compatible(&specs) {
+                    conflicts.push(SpecConflict {
+                        first: (*first).clone(),
+                        second: (*second).clone(),
+                    });
+                }
+            }
+        }
+    }
+    conflicts.sort_by_key(|c| c.to_string());
+    conflicts
+}
+
+// The package an editable requirement installs, `-e path/to/pkg` or
+// `--editable file:///repo/pkg#egg=name`, named by its `#egg=` fragment or
+// else its directory. None for the project itself, `-e .`
+fn editable_dependency(entry: &str) -> Option<Dependency> {
+    let target = entry
+        .strip_prefix("--editable")
+        .or_else(|| entry.strip_prefix("-e"))?
+        .trim_start_matches(['=', ' ']);
+    let name = match target.split_once("#egg=") {
+        Some((_, egg)) => egg.split('&').next()?,
+        None => target
+            .split(['#', '?'])
+            .next()?
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()?,
+    };
+    match name.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        true => Dependency::parse(name),
+        false => None,
+    }
+}
+
+// The array new dependencies for `section` are appended to, created when
+// missing
+fn section_array<'d>(doc: &'d mut DocumentMut, section: &Section) -> Option<&'d mut Array> {
+    let (table, name) = match section {
+        Section::Runtime => (doc.get_mut("project")?.as_table_like_mut()?, "dependencies"),
+        Section::Group(name) => (
+            doc.entry("dependency-groups")
+                .or_insert(table())
+                .as_table_like_mut()?,
+            name.as_str(),
+        ),
+        Section::Extra(name) => (
+            doc.get_mut("project")?
+                .as_table_like_mut()?
+                .entry("optional-dependencies")
+                .or_insert(table())
+                .as_table_like_mut()?,
+            name.as_str(),
+        ),
+    };
+    table
+        .entry(name)
+        .or_insert(value(Array::new()))
+        .as_array_mut()
+}
+
+// The Poetry table new dependencies for `section` are added to, created when
+// missing. Poetry extras are flags on runtime dependencies, there's no table
+fn poetry_table<'d>(doc: &'d mut DocumentMut, section: &Section) -> Option<&'d mut dyn TableLike> {
+    let poetry = doc
+        .get_mut("tool")?
+        .get_mut("poetry")?
+        .as_table_like_mut()?;
+    let parent = match section {
+        Section::Runtime => poetry,
+        Section::Group(name) => poetry
+            .entry("group")
+            .or_insert(implicit_table())
+            .as_table_like_mut()?
+            .entry(name)
+            .or_insert(implicit_table())
+            .as_table_like_mut()?,
+        Section::Extra(_) => return None,
+    };
+    parent
+        .entry("dependencies")
+        .or_insert(table())
+        .as_table_like_mut()
+}
+
+// A table that only gets a header when it has keys of its own
+fn implicit_table() -> Item {
+    let mut table = Table::new();
+    table.set_implicit(true);
+    Item::Table(table)
+}
+
+const SYNC_COMMENT: &str = "# last synced by pydepsync";
+
+// Replace any previous sync comment in a key's decor, keeping the other comments
+fn set_sync_comment(decor: &mut Decor, date: &str, count: usize) {
+    let existing = decor.prefix().and_then(|p| p.as_str()).unwrap_or("");
+    let mut prefix: String = existing
+        .split_inclusive('\n')
+        .filter(|line| !line.trim_start().starts_with(SYNC_COMMENT))
+        .collect();
+    prefix.push_str(&format!(
+        "{SYNC_COMMENT} on {date}, managing {count} dependencies\n"
+    ));
+    decor.set_prefix(prefix);
+}
+
+// The current UTC date as YYYY-MM-DD
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // Civil date from days since the unix epoch, proleptic gregorian
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
 pub fn write(
-    path: &PathBuf,
-    mut pyproject: PyProject,
-    new_deps: HashSet<Dependency>,
+    path: &Path,
+    pyproject: PyProject,
+    new_deps: HashMap<Section, HashSet<Dependency>>,
+    options: &WriteOptions,
+) -> Result<(), io::Error> {
+    let updated_contents = render(pyproject, new_deps, options)?;
+    write_atomically(path, &updated_contents)
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it over
+/// `path`, so a run killed halfway leaves the old file rather than a
+/// truncated one
+fn write_atomically(path: &Path, contents: &str) -> Result<(), io::Error> {
+    // Replace what a symlink points at, not the link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(contents.as_bytes())?;
+    // Temporary files are only readable by their owner
+    if let Ok(metadata) = fs::metadata(&path) {
+        file.as_file().set_permissions(metadata.permissions())?;
+    }
+    file.as_file().sync_all()?;
+    file.persist(&path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn add_pep621(
+    doc: &mut DocumentMut,
+    mut new_deps: HashMap<Section, HashSet<Dependency>>,
+    options: &WriteOptions,
 ) -> Result<(), io::Error> {
-    // Constrcuct a new dependency set that we will write back to pyproject
-    // that contains the existing ones and anything new
-    let mut arr = Array::new();
-    for dep in new_deps {
-        info!("Adding: {dep}");
-        arr.push(dep.to_dependency_repr());
+    // Runtime dependencies and extras live under [project]. Creating it would
+    // turn a setup.py project into a PEP 621 one missing its name, which
+    // build backends refuse, so it has to exist already
+    let needs_project = new_deps
+        .iter()
+        .any(|(section, deps)| !matches!(section, Section::Group(_)) && !deps.is_empty());
+    if needs_project && doc.get("project").and_then(Item::as_table_like).is_none() {
+        let mut names: Vec<String> = new_deps
+            .iter()
+            .filter(|(section, _)| !matches!(section, Section::Group(_)))
+            .flat_map(|(_, deps)| deps.iter().map(|d| d.name()))
+            .collect();
+        names.sort();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "pyproject.toml has no [project] table to add {} to, add one with a name \
+                 (or a [tool.poetry.dependencies] table for a Poetry project)",
+                names.join(", ")
+            ),
+        ));
     }
-    for dep in pyproject.deps {
-        arr.push(dep.to_dependency_repr());
+
+    let mut sections: Vec<_> = new_deps
+        .keys()
+        .filter(|s| **s != Section::Runtime)
+        .cloned()
+        .collect();
+    sections.sort();
+    for section in sections {
+        let deps = new_deps.remove(&section).unwrap_or_default();
+        let Some(arr) = section_array(doc, &section) else {
+            warn!("Can't write to {section} in pyproject.toml, skipping its dependencies");
+            continue;
+        };
+        for dep in options.render_all(deps) {
+            info!("Adding to {section}: {dep}");
+            append(arr, dep);
+        }
+        options.sort_order.sort(arr);
     }
-    // Insert into project table
-    if let Some(project) = pyproject.toml_document.get_mut("project") {
-        if let Some(table) = project.as_table_mut() {
-            table.insert("dependencies", value(arr));
+
+    // New dependencies are added to the existing array, so the entries that
+    // are already there keep their formatting and comments
+    let deps = new_deps.remove(&Section::Runtime).unwrap_or_default();
+    if let Some(arr) = section_array(doc, &Section::Runtime) {
+        for dep in options.render_all(deps) {
+            info!("Adding: {dep}");
+            append(arr, dep);
+        }
+        options.sort_order.sort(arr);
+        let count = arr.len();
+        if options.sync_comment {
+            if let Some(mut key) = doc
+                .get_mut("project")
+                .and_then(Item::as_table_mut)
+                .and_then(|t| t.key_mut("dependencies"))
+            {
+                set_sync_comment(key.leaf_decor_mut(), &today(), count);
+            }
         }
     }
+    Ok(())
+}
+
+// Poetry tables are keyed by package name, new dependencies are added with
+// their version specs translated to Poetry constraints
+fn add_poetry(
+    doc: &mut DocumentMut,
+    new_deps: HashMap<Section, HashSet<Dependency>>,
+    options: &WriteOptions,
+) {
+    let mut sections: Vec<_> = new_deps.into_iter().collect();
+    sections.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (section, deps) in sections {
+        let Some(table) = poetry_table(doc, &section) else {
+            warn!("Can't write to {section} in a Poetry project, skipping its dependencies");
+            continue;
+        };
+        // Rendered first so transforms and the spec template still apply
+        for rendered in options.render_all(deps) {
+            let Some(dep) = Dependency::parse(&rendered) else {
+                warn!("Can't write {rendered} as a Poetry dependency, skipping it");
+                continue;
+            };
+            match section {
+                Section::Runtime => info!("Adding: {dep}"),
+                _ => info!("Adding to {section}: {dep}"),
+            }
+            table.insert(&dep.name(), value(poetry::entry(&dep)));
+        }
+    }
+}
+
+/// The contents pyproject.toml would have with the new dependencies added,
+/// checked with [`validate`] so a broken file is never written
+pub fn render(
+    mut pyproject: PyProject,
+    new_deps: HashMap<Section, HashSet<Dependency>>,
+    options: &WriteOptions,
+) -> Result<String, io::Error> {
+    match pyproject.layout {
+        Layout::Pep621 | Layout::Pdm | Layout::Hatch => {
+            add_pep621(&mut pyproject.toml_document, new_deps, options)?
+        }
+        Layout::Poetry => add_poetry(&mut pyproject.toml_document, new_deps, options),
+    }
+    let updated_contents = pyproject.toml_document.to_string();
+    if !options.reformat {
+        validate(&updated_contents)?;
+        return Ok(updated_contents);
+    }
     let updated_contents = format(
-        &pyproject.toml_document.to_string(),
+        &updated_contents,
         Options {
             align_entries: true,
             align_comments: true,
@@ -135,21 +854,69 @@ pub fn write(
             indent_string: "    ".into(),
             trailing_newline: false,
             reorder_keys: false,
-            reorder_arrays: true,
+            reorder_arrays: false,
             allowed_blank_lines: 2,
             crlf: false,
         },
     );
-    // Write back to file
-    fs::write(path, updated_contents).unwrap();
+    validate(&updated_contents)?;
+    Ok(updated_contents)
+}
+
+/// Check that pyproject.toml contents parse as TOML and that every
+/// dependency in them round-trips through [`Dependency::parse`]
+pub fn validate(contents: &str) -> Result<(), io::Error> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let doc = contents
+        .parse::<DocumentMut>()
+        .map_err(|e| invalid(format!("pyproject.toml is not valid TOML: {e}")))?;
+
+    let mut arrays: Vec<(String, &Array)> = Vec::new();
+    if let Some(deps) = doc
+        .get("project")
+        .and_then(|p| p.get("dependencies"))
+        .and_then(Item::as_array)
+    {
+        arrays.push(("project.dependencies".to_string(), deps));
+    }
+    let tables = [
+        (
+            "project.optional-dependencies",
+            doc.get("project")
+                .and_then(|p| p.get("optional-dependencies")),
+        ),
+        ("dependency-groups", doc.get("dependency-groups")),
+    ];
+    for (prefix, table) in tables {
+        for (name, item) in table
+            .and_then(Item::as_table_like)
+            .into_iter()
+            .flat_map(|t| t.iter())
+        {
+            if let Some(array) = item.as_array() {
+                arrays.push((format!("{prefix}.{name}"), array));
+            }
+        }
+    }
+
+    // Groups can also hold `{include-group = "..."}` tables, only strings are requirements
+    for (section, array) in arrays {
+        for line in array.iter().filter_map(|v| v.as_str()) {
+            let round_trips = Dependency::parse(line).is_some_and(|dep| {
+                let repr = dep.to_dependency_repr();
+                Dependency::parse(&repr).is_some_and(|d| d.to_dependency_repr() == repr)
+            });
+            if !round_trips {
+                return Err(invalid(format!("invalid dependency {line:?} in {section}")));
+            }
+        }
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
 
     fn setup_toml_file(content: &str) -> NamedTempFile {
         let mut file = NamedTempFile::new().unwrap();
@@ -163,6 +930,10 @@ mod tests {
             deps: HashSet::new(),
             optional_deps: HashSet::new(),
             toml_document: DocumentMut::new(),
+            conflicts: Vec::new(),
+            section_deps: Vec::new(),
+            requires_python: None,
+            layout: Layout::Pep621,
         };
         let all_deps = pyproject.all_deps();
         assert_eq!(all_deps.len(), 0, "Empty deps should return empty set");
@@ -179,6 +950,10 @@ mod tests {
             deps,
             optional_deps,
             toml_document: DocumentMut::new(),
+            conflicts: Vec::new(),
+            section_deps: Vec::new(),
+            requires_python: None,
+            layout: Layout::Pep621,
         };
         let all_deps = pyproject.all_deps();
 
@@ -202,6 +977,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = read(&dir.path().join("pyproject.toml")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let file = setup_toml_file("[project\ndependencies = []\n");
+        let err = read(&file.path().to_path_buf()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
     #[test]
     fn test_read_basic_deps() {
         let toml_content = r#"
@@ -226,6 +1011,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_requires_python() {
+        let file = setup_toml_file("[project]\nrequires-python = \">=3.9\"\n");
+        let pyproject = read(&file.path().to_path_buf()).unwrap();
+        assert_eq!(pyproject.requires_python(), Some(">=3.9"));
+
+        let file = setup_toml_file("[project]\ndependencies = []\n");
+        let pyproject = read(&file.path().to_path_buf()).unwrap();
+        assert_eq!(pyproject.requires_python(), None);
+    }
+
     #[test]
     fn test_read_optional_deps() {
         let toml_content = r#"
@@ -289,6 +1085,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_discover_walks_up_to_ancestor() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src").join("app");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(discover(&nested), None);
+
+        fs::write(dir.path().join("pyproject.toml"), "[project]\n").unwrap();
+        assert_eq!(discover(&nested), Some(dir.path().join("pyproject.toml")));
+
+        // The closest pyproject.toml wins
+        fs::write(nested.join("pyproject.toml"), "[project]\n").unwrap();
+        assert_eq!(discover(&nested), Some(nested.join("pyproject.toml")));
+    }
+
     #[test]
     fn test_read_file_not_found() {
         let path = PathBuf::from("nonexistent.toml");
@@ -300,4 +1111,685 @@ mod tests {
             "Error should be NotFound"
         );
     }
+
+    #[test]
+    fn test_read_conflicting_specs_across_sections() {
+        let toml_content = r#"
+            [project]
+            dependencies = ["requests>=2", "django~=4.2"]
+
+            [project.optional-dependencies]
+            web = ["django>=4.2.1"]
+
+            [dependency-groups]
+            legacy = ["requests<2"]
+        "#;
+        let file = setup_toml_file(toml_content);
+        let pyproject = read(&file.path().to_path_buf()).unwrap();
+
+        assert_eq!(
+            pyproject.conflicts().len(),
+            1,
+            "{:?}",
+            pyproject.conflicts()
+        );
+        let conflict = &pyproject.conflicts()[0];
+        let sections = [conflict.first.0.as_str(), conflict.second.0.as_str()];
+        assert!(sections.contains(&"project.dependencies"));
+        assert!(sections.contains(&"dependency-groups.legacy"));
+        assert!(conflict.to_string().contains("requests>=2"));
+        assert!(conflict.to_string().contains("requests<2"));
+    }
+
+    #[test]
+    fn test_write_to_sections() {
+        let toml_content = r#"
+[project]
+name = "example"
+dependencies = ["django"]
+
+[project.optional-dependencies]
+docs = ["sphinx"]
+"#;
+        let file = setup_toml_file(toml_content);
+        let path = file.path().to_path_buf();
+        let pyproject = read(&path).unwrap();
+        let new_deps = HashMap::from([
+            (
+                Section::Runtime,
+                HashSet::from([Dependency::parse("requests").unwrap()]),
+            ),
+            (
+                Section::Group("test".to_string()),
+                HashSet::from([Dependency::parse("pytest").unwrap()]),
+            ),
+            (
+                Section::Extra("docs".to_string()),
+                HashSet::from([Dependency::parse("furo").unwrap()]),
+            ),
+        ]);
+        write(&path, pyproject, new_deps, &WriteOptions::default()).unwrap();
+
+        let pyproject = read(&path).unwrap();
+        assert_eq!(
+            pyproject.deps,
+            HashSet::from([
+                Dependency::parse("django").unwrap(),
+                Dependency::parse("requests").unwrap()
+            ])
+        );
+        let doc = &pyproject.toml_document;
+        let names = |item: &Item| -> Vec<String> {
+            item.as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(&doc["project"]["optional-dependencies"]["docs"]),
+            vec!["sphinx", "furo"]
+        );
+        assert_eq!(names(&doc["dependency-groups"]["test"]), vec!["pytest"]);
+    }
+
+    #[test]
+    fn test_default_keeps_the_existing_order() {
+        let toml_content =
+            "[project]\nname = \"example\"\ndependencies = [\"requests\", \"Django\"]\n";
+        let file = setup_toml_file(toml_content);
+        let path = file.path().to_path_buf();
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([
+                Dependency::parse("rich").unwrap(),
+                Dependency::parse("attrs").unwrap(),
+            ]),
+        )]);
+        write(
+            &path,
+            read(&path).unwrap(),
+            new_deps,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("dependencies = [\"requests\", \"Django\", \"attrs\", \"rich\"]"));
+    }
+
+    #[test]
+    fn test_write_with_spec_template() {
+        let file = setup_toml_file("[project]\nname = \"example\"\ndependencies = [\"django\"]\n");
+        let path = file.path().to_path_buf();
+        let pyproject = read(&path).unwrap();
+        let options = WriteOptions {
+            spec_template: Some(SpecTemplate::parse("{name} >= {version}").unwrap()),
+            ..WriteOptions::default()
+        };
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([Dependency::parse("requests~=2.32.3").unwrap()]),
+        )]);
+        write(&path, pyproject, new_deps, &options).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("\"requests >= 2.32.3\""), "{written}");
+        // Existing dependencies are left as they are
+        assert!(written.contains("\"django\""), "{written}");
+    }
+
+    #[test]
+    fn test_sync_comment_is_updated_in_place() {
+        let toml_content = r#"
+[project]
+name = "example"
+# runtime dependencies
+dependencies = ["django"]
+"#;
+        let file = setup_toml_file(toml_content);
+        let path = file.path().to_path_buf();
+        let options = WriteOptions {
+            sync_comment: true,
+            ..WriteOptions::default()
+        };
+        for name in ["requests", "numpy"] {
+            let pyproject = read(&path).unwrap();
+            let new_deps = HashMap::from([(
+                Section::Runtime,
+                HashSet::from([Dependency::parse(name).unwrap()]),
+            )]);
+            write(&path, pyproject, new_deps, &options).unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.matches(SYNC_COMMENT).count(), 1);
+        assert!(contents.contains(&format!(
+            "{SYNC_COMMENT} on {}, managing 3 dependencies\n",
+            today()
+        )));
+        assert!(contents.contains("# runtime dependencies"));
+        assert_eq!(read(&path).unwrap().deps.len(), 3);
+    }
+
+    #[test]
+    fn test_today() {
+        let date = today();
+        assert_eq!(date.len(), 10);
+        assert!(date.as_str() > "2024-01-01");
+    }
+
+    #[test]
+    fn test_render_rejects_invalid_dependencies() {
+        let file = setup_toml_file("[project]\nname = \"example\"\ndependencies = [\"django\"]\n");
+        let path = file.path().to_path_buf();
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([Dependency::parse("requests~=2.31").unwrap()]),
+        )]);
+        // A template that renders something that isn't a requirement
+        let options = WriteOptions {
+            spec_template: Some(SpecTemplate::parse("=={version} {name}").unwrap()),
+            ..WriteOptions::default()
+        };
+        let err = write(&path, read(&path).unwrap(), new_deps, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("\"==2.31 requests\""), "{err}");
+        assert!(err.to_string().contains("project.dependencies"), "{err}");
+        // Nothing was written
+        assert_eq!(read(&path).unwrap().deps.len(), 1);
+    }
+
+    #[test]
+    fn test_writes_atomically() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pyproject.toml");
+        fs::write(&path, "[project]\nname = \"app\"\ndependencies = []\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([Dependency::parse("requests").unwrap()]),
+        )]);
+        write(
+            &path,
+            read(&path).unwrap(),
+            new_deps,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(read(&path).unwrap().deps.len(), 1);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o644);
+        // The temporary file was renamed over pyproject.toml, nothing is left behind
+        let files: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_refuses_to_write_without_a_project_table() {
+        let toml_content = "[tool.black]\nline-length = 100\n";
+        let file = setup_toml_file(toml_content);
+        let path = file.path().to_path_buf();
+        let before = fs::read_to_string(&path).unwrap();
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([Dependency::parse("requests").unwrap()]),
+        )]);
+        let err = write(
+            &path,
+            read(&path).unwrap(),
+            new_deps,
+            &WriteOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("no [project] table"), "{err}");
+        assert!(err.to_string().contains("requests"), "{err}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+
+        // Dependency groups are top level, they don't need one
+        let new_deps = HashMap::from([(
+            Section::Group("dev".to_string()),
+            HashSet::from([Dependency::parse("pytest").unwrap()]),
+        )]);
+        write(
+            &path,
+            read(&path).unwrap(),
+            new_deps,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let doc = read(&path).unwrap().toml_document;
+        assert_eq!(doc["dependency-groups"]["dev"][0].as_str(), Some("pytest"));
+        assert!(doc.get("project").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(
+            "[project]\ndependencies = [\"django>=4.2; python_version >= '3.10'\"]\n"
+        )
+        .is_ok());
+        assert!(
+            validate("[dependency-groups]\ndev = [\"pytest\", {include-group = \"lint\"}]\n")
+                .is_ok()
+        );
+        assert!(validate("[project\n").is_err());
+        assert!(validate("[project.optional-dependencies]\ndocs = [\"\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_write_keeps_version_ranges() {
+        let file = setup_toml_file(
+            "[project]\nname = \"example\"\ndependencies = [\"Django>=3.2,<4.0\"]\n",
+        );
+        let path = file.path().to_path_buf();
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([Dependency::parse("httpx~=0.27").unwrap()]),
+        )]);
+        write(
+            &path,
+            read(&path).unwrap(),
+            new_deps,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("\"Django>=3.2,<4.0\""), "{contents}");
+    }
+
+    #[test]
+    fn test_untouched_pyproject_round_trips() {
+        let toml_content = r#"[project]
+name = "example"
+requires-python = ">=3.8"
+dependencies = [
+    "Django >= 3.2, < 4.0",
+    "foo>=1,<2; python_version<'3.9'",
+    "numpy!=1.25.0,>=1.24",
+    'requests[socks,security]~=2.31; sys_platform == "linux"',
+]
+
+[project.optional-dependencies]
+docs = [ "sphinx>=7" ]
+"#;
+        let file = setup_toml_file(toml_content);
+        let path = file.path().to_path_buf();
+        let before = fs::read_to_string(&path).unwrap();
+        write(
+            &path,
+            read(&path).unwrap(),
+            HashMap::new(),
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        // Byte for byte, down to the blank line at the end
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+
+        // Reformatting is opt-in
+        let options = WriteOptions {
+            reformat: true,
+            ..WriteOptions::default()
+        };
+        write(&path, read(&path).unwrap(), HashMap::new(), &options).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), toml_content);
+    }
+
+    #[test]
+    fn test_only_added_entries_change() {
+        for (before, after) in [
+            (
+                "[project]\ndependencies = [\n  \"numpy\",\n  \"attrs\"\n]\n",
+                "[project]\ndependencies = [\n  \"numpy\",\n  \"attrs\",\n  \"rich\"\n]\n",
+            ),
+            (
+                "[project]\ndependencies = [\n    \"numpy\",\n]\n",
+                "[project]\ndependencies = [\n    \"numpy\",\n    \"rich\",\n]\n",
+            ),
+            (
+                "[project]\ndependencies = [\"numpy\"]\n",
+                "[project]\ndependencies = [\"numpy\", \"rich\"]\n",
+            ),
+            (
+                "[project]\ndependencies = [\n]\n",
+                "[project]\ndependencies = [\n    \"rich\",\n]\n",
+            ),
+        ] {
+            let file = NamedTempFile::new().unwrap();
+            let path = file.path().to_path_buf();
+            fs::write(&path, before).unwrap();
+            let new_deps = HashMap::from([(
+                Section::Runtime,
+                HashSet::from([Dependency::parse("rich").unwrap()]),
+            )]);
+            let options = WriteOptions {
+                sort_order: SortOrder::None,
+                ..WriteOptions::default()
+            };
+            write(&path, read(&path).unwrap(), new_deps, &options).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), after);
+        }
+    }
+
+    #[test]
+    fn test_write_keeps_comments() {
+        let toml_content = r#"[project]
+name = "example"
+dependencies = [
+    # web
+    "requests>=2", # needed for retries
+    "django",
+    "attrs", # pinned by the lockfile
+]
+"#;
+        let file = setup_toml_file(toml_content);
+        let path = file.path().to_path_buf();
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([Dependency::parse("numpy").unwrap()]),
+        )]);
+        write(
+            &path,
+            read(&path).unwrap(),
+            new_deps,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().map(str::trim).collect();
+        let line = |dep: &str| *lines.iter().find(|l| l.starts_with(dep)).unwrap();
+        assert!(
+            line("\"requests>=2\"").ends_with("# needed for retries"),
+            "{contents}"
+        );
+        assert!(
+            line("\"attrs\"").ends_with("# pinned by the lockfile"),
+            "{contents}"
+        );
+        assert_eq!(line("\"django\""), "\"django\",", "{contents}");
+        assert_eq!(line("\"numpy\""), "\"numpy\",", "{contents}");
+        // The comment above an entry moves with it
+        let web = lines.iter().position(|l| *l == "# web").unwrap();
+        assert!(lines[web + 1].starts_with("\"requests>=2\""), "{contents}");
+    }
+
+    #[test]
+    fn test_direct_references_are_declared() {
+        let file = setup_toml_file(
+            "[project]\nname = \"example\"\ndependencies = [\"mypkg @ git+https://github.com/me/mypkg@v1.0\"]\n",
+        );
+        let path = file.path().to_path_buf();
+        let pyproject = read(&path).unwrap();
+        let dep = pyproject.deps.iter().next().unwrap();
+        assert_eq!(dep.url(), Some("git+https://github.com/me/mypkg@v1.0"));
+        let new_deps = HashMap::from([(
+            Section::Runtime,
+            HashSet::from([Dependency::parse("requests~=2.32").unwrap()]),
+        )]);
+        write(&path, pyproject, new_deps, &WriteOptions::default()).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("\"mypkg @ git+https://github.com/me/mypkg@v1.0\""));
+    }
+
+    #[test]
+    fn test_remove_dependency() {
+        let file = setup_toml_file(
+            "[project]\nname = \"example\"\ndependencies = [\"Django~=5.0\", \"python-dateutil\", \"requests\"]\n",
+        );
+        let path = file.path().to_path_buf();
+        let mut pyproject = read(&path).unwrap();
+        pyproject.remove(&Dependency::parse("python_dateutil").unwrap());
+        write(&path, pyproject, HashMap::new(), &WriteOptions::default()).unwrap();
+        let pyproject = read(&path).unwrap();
+        assert_eq!(
+            pyproject.deps,
+            HashSet::from([
+                Dependency::parse("django").unwrap(),
+                Dependency::parse("requests").unwrap()
+            ])
+        );
+
+        let file = setup_toml_file(
+            "[tool.poetry]\nname = \"example\"\n\n[tool.poetry.dependencies]\npython = \"^3.9\"\nDjango = \"^4.2\"\nrequests = \"*\"\n",
+        );
+        let path = file.path().to_path_buf();
+        let mut pyproject = read(&path).unwrap();
+        pyproject.remove(&Dependency::parse("django").unwrap());
+        write(&path, pyproject, HashMap::new(), &WriteOptions::default()).unwrap();
+        let pyproject = read(&path).unwrap();
+        assert_eq!(
+            pyproject.deps,
+            HashSet::from([Dependency::parse("requests").unwrap()])
+        );
+        assert_eq!(pyproject.requires_python(), Some(">=3.9,<4.0"));
+    }
+
+    #[test]
+    fn test_poetry_layout() {
+        let toml_content = r#"[tool.poetry]
+name = "example"
+
+[tool.poetry.dependencies]
+python = "^3.9"
+django = "^4.2"
+requests = { version = "~2.31", extras = ["socks"] }
+
+[tool.poetry.group.test.dependencies]
+pytest = "*"
+"#;
+        let file = setup_toml_file(toml_content);
+        let path = file.path().to_path_buf();
+        let pyproject = read(&path).unwrap();
+        assert_eq!(pyproject.layout(), Layout::Poetry);
+        assert_eq!(pyproject.requires_python(), Some(">=3.9,<4.0"));
+        assert_eq!(
+            pyproject.deps,
+            HashSet::from([
+                Dependency::parse("django").unwrap(),
+                Dependency::parse("requests[socks]").unwrap()
+            ])
+        );
+        assert!(pyproject
+            .all_deps()
+            .contains(&Dependency::parse("pytest").unwrap()));
+
+        let new_deps = HashMap::from([
+            (
+                Section::Runtime,
+                HashSet::from([
+                    Dependency::parse("httpx~=0.27").unwrap(),
+                    Dependency::parse("numpy~=2.2.4; python_version >= '3.10'").unwrap(),
+                ]),
+            ),
+            (
+                Section::Group("test".to_string()),
+                HashSet::from([Dependency::parse("hypothesis~=6.1").unwrap()]),
+            ),
+            (
+                Section::Group("docs".to_string()),
+                HashSet::from([Dependency::parse("sphinx==8.2.3").unwrap()]),
+            ),
+        ]);
+        write(&path, pyproject, new_deps, &WriteOptions::default()).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let doc = contents.parse::<DocumentMut>().unwrap();
+        let poetry = &doc["tool"]["poetry"];
+        assert_eq!(
+            poetry["dependencies"]["httpx"].as_str(),
+            Some(">=0.27,<1.0")
+        );
+        assert_eq!(
+            poetry["dependencies"]["numpy"]["version"].as_str(),
+            Some("~2.2.4")
+        );
+        assert_eq!(
+            poetry["dependencies"]["numpy"]["markers"].as_str(),
+            Some("python_version >= '3.10'")
+        );
+        assert_eq!(
+            poetry["group"]["test"]["dependencies"]["hypothesis"].as_str(),
+            Some("^6.1")
+        );
+        assert_eq!(
+            poetry["group"]["docs"]["dependencies"]["sphinx"].as_str(),
+            Some("8.2.3")
+        );
+        assert!(!contents.contains("[project]"), "{contents}");
+        assert!(!contents.contains("[tool.poetry.group]"), "{contents}");
+        assert_eq!(read(&path).unwrap().deps.len(), 4);
+    }
+
+    #[test]
+    fn test_pdm_and_hatch_layouts() {
+        let pdm = r#"[project]
+name = "example"
+dependencies = ["django"]
+
+[tool.pdm.dev-dependencies]
+test = ["pytest>=8", "-e file:///${PROJECT_ROOT}/plugins/local", "-e file:///${PROJECT_ROOT}/"]
+lint = ["ruff", "-e file:///${PROJECT_ROOT}/tools/lint#egg=corp-lint"]
+"#;
+        let hatch = r#"[project]
+name = "example"
+dependencies = ["django"]
+
+[tool.hatch.envs.default]
+dependencies = ["pytest>=8"]
+
+[tool.hatch.envs.lint]
+detached = true
+extra-dependencies = ["ruff", "--editable=./plugins/local", "-e ./tools/lint#egg=corp-lint"]
+"#;
+        for (toml_content, layout) in [(pdm, Layout::Pdm), (hatch, Layout::Hatch)] {
+            let file = setup_toml_file(toml_content);
+            let pyproject = read(&file.path().to_path_buf()).unwrap();
+            assert_eq!(pyproject.layout(), layout);
+            assert_eq!(
+                pyproject.deps,
+                HashSet::from([Dependency::parse("django").unwrap()])
+            );
+            assert_eq!(
+                pyproject.all_deps(),
+                HashSet::from([
+                    Dependency::parse("django").unwrap(),
+                    Dependency::parse("pytest").unwrap(),
+                    Dependency::parse("ruff").unwrap(),
+                    // Editable local packages
+                    Dependency::parse("local").unwrap(),
+                    Dependency::parse("corp-lint").unwrap(),
+                ]),
+                "{layout:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_local_packages_count_as_declared() {
+        let toml_content = r#"[project]
+name = "example"
+dependencies = ["mylib @ file:///home/me/src/mylib", "api-client"]
+
+[tool.uv.sources]
+api-client = { workspace = true }
+shared = { path = "../shared", editable = true }
+"#;
+        let file = setup_toml_file(toml_content);
+        let pyproject = read(&file.path().to_path_buf()).unwrap();
+        let names: HashSet<String> = pyproject.all_deps().iter().map(|d| d.name()).collect();
+        assert_eq!(
+            names,
+            HashSet::from([
+                "mylib".to_string(),
+                "api-client".to_string(),
+                "shared".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let toml_content =
+            "[project]\nname = \"example\"\ndependencies = [\"numpy\", \"Django\", \"attrs\"]\n";
+        let expected = [
+            (
+                SortOrder::AlphaCi,
+                vec!["attrs", "Django", "numpy", "Pillow"],
+            ),
+            (
+                SortOrder::AlphaCs,
+                vec!["Django", "Pillow", "attrs", "numpy"],
+            ),
+            (SortOrder::None, vec!["numpy", "Django", "attrs", "Pillow"]),
+        ];
+        for (sort_order, expected) in expected {
+            let file = setup_toml_file(toml_content);
+            let path = file.path().to_path_buf();
+            let new_deps = HashMap::from([(
+                Section::Runtime,
+                HashSet::from([Dependency::parse("Pillow").unwrap()]),
+            )]);
+            let options = WriteOptions {
+                sort_order,
+                ..WriteOptions::default()
+            };
+            write(&path, read(&path).unwrap(), new_deps, &options).unwrap();
+
+            let pyproject = read(&path).unwrap();
+            let deps: Vec<&str> = pyproject.toml_document["project"]["dependencies"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|v| v.as_str())
+                .collect();
+            assert_eq!(deps, expected, "{sort_order:?}");
+        }
+    }
+
+    #[test]
+    fn test_appended_dependencies_are_sorted() {
+        let toml_content =
+            "[project]\nname = \"example\"\ndependencies = [\"numpy\", \"Django\"]\n";
+        for _ in 0..5 {
+            let file = setup_toml_file(toml_content);
+            let path = file.path().to_path_buf();
+            let new_deps = HashMap::from([(
+                Section::Runtime,
+                ["rich", "Pillow", "attrs", "celery"]
+                    .into_iter()
+                    .map(|d| Dependency::parse(d).unwrap())
+                    .collect(),
+            )]);
+            let options = WriteOptions {
+                sort_order: SortOrder::None,
+                ..WriteOptions::default()
+            };
+            write(&path, read(&path).unwrap(), new_deps, &options).unwrap();
+            let pyproject = read(&path).unwrap();
+            let deps: Vec<&str> = pyproject.toml_document["project"]["dependencies"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|v| v.as_str())
+                .collect();
+            assert_eq!(
+                deps,
+                ["numpy", "Django", "attrs", "celery", "Pillow", "rich"]
+            );
+        }
+
+        #[derive(Deserialize)]
+        struct Policy {
+            sort_order: SortOrder,
+        }
+        let policy = |name: &str| {
+            toml::from_str::<Policy>(&format!("sort_order = \"{name}\""))
+                .unwrap()
+                .sort_order
+        };
+        assert_eq!(policy("alphabetical"), SortOrder::AlphaCi);
+        assert_eq!(policy("append"), SortOrder::None);
+        assert_eq!(policy("off"), SortOrder::None);
+    }
 }
@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use log::debug;
+
+use crate::dependency::Dependency;
+
+/// The requirements of a requirements.txt. Comments and pip options like
+/// `-r other.txt`, `-e .` or `--index-url` are skipped
+pub fn read(path: &PathBuf) -> Result<HashSet<Dependency>, io::Error> {
+    let contents = fs::read_to_string(path)?;
+    let mut deps = HashSet::new();
+    for line in contents.lines() {
+        // Like pip, a comment starts a line or follows whitespace, `#` in a url isn't one.
+        // Per-requirement options like `--hash=sha256:...` follow the requirement
+        let line = match line.find(" #").or(line.find("\t#")) {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let line = match line.find(" --").or(line.find("\t--")) {
+            Some(i) => &line[..i],
+            None => line,
+        }
+        .trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
+            continue;
+        }
+        match Dependency::parse(line) {
+            Some(dep) => {
+                deps.insert(dep);
+            }
+            None => debug!("Skipping requirement {line:?} in {}", path.display()),
+        }
+    }
+    Ok(deps)
+}
+
+/// Add dependencies to the end of a requirements.txt, sorted, leaving the
+/// existing lines as they are. The file is created when it doesn't exist
+pub fn append(path: &PathBuf, deps: &HashSet<Dependency>) -> Result<(), io::Error> {
+    let mut contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    let mut lines: Vec<String> = deps.iter().map(|d| d.to_dependency_repr()).collect();
+    lines.sort_by_key(|l| l.to_lowercase());
+    for line in lines {
+        contents += &line;
+        contents.push('\n');
+    }
+    fs::write(path, contents)
+}
+
+/// Write dependencies as a requirements.txt, one PEP 508 requirement per line
+pub fn write(path: &PathBuf, deps: &HashSet<Dependency>) -> Result<(), io::Error> {
+    let mut lines: Vec<String> = deps.iter().map(|d| d.to_dependency_repr()).collect();
+    // Sorted so regenerating the file gives a stable diff
+    lines.sort_by_key(|l| l.to_lowercase());
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_requirements() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requirements.txt");
+        let deps = HashSet::from([
+            Dependency::parse("requests~=2.32.3").unwrap(),
+            Dependency::parse("Django[argon2]>=4.2; python_version >= '3.10'").unwrap(),
+            Dependency::parse("attrs").unwrap(),
+        ]);
+        write(&path, &deps).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "attrs\nDjango[argon2]>=4.2; python_version >= '3.10'\nrequests~=2.32.3\n"
+        );
+    }
+
+    #[test]
+    fn test_read_and_append_requirements() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requirements.txt");
+        let contents = "# runtime\n-r base.txt\n-e .\n--index-url https://pypi.org/simple\n\
+                        Django>=4.2,<5  # LTS\nrequests[socks]\n\n\
+                        torch==2.1.0+cpu --hash=sha256:abc \\\n    --hash=sha256:def\n\
+                        app @ https://example.com/app.whl#sha256=abc";
+        fs::write(&path, contents).unwrap();
+        assert_eq!(
+            read(&path).unwrap(),
+            HashSet::from([
+                Dependency::parse("django").unwrap(),
+                Dependency::parse("requests[socks]").unwrap(),
+                Dependency::parse("app").unwrap(),
+                Dependency::parse("torch").unwrap(),
+            ])
+        );
+
+        let new_deps = HashSet::from([
+            Dependency::parse("numpy~=2.2.4").unwrap(),
+            Dependency::parse("attrs").unwrap(),
+        ]);
+        append(&path, &new_deps).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{contents}\nattrs\nnumpy~=2.2.4\n")
+        );
+    }
+}
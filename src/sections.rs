@@ -0,0 +1,497 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::dependency::Dependency;
+use crate::engine::DetectedDependency;
+use crate::pyproject::PyProject;
+
+/// Where in pyproject.toml a new dependency is written
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Section {
+    /// `project.dependencies`
+    Runtime,
+    /// A group in `[dependency-groups]` (PEP 735)
+    Group(String),
+    /// An extra in `[project.optional-dependencies]`
+    Extra(String),
+}
+
+impl TryFrom<String> for Section {
+    type Error = SectionRuleError;
+
+    /// Parse `runtime`, `group:<name>` or `extra:<name>`
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.split_once(':') {
+            None if value == "runtime" => Ok(Section::Runtime),
+            Some(("group", name)) if !name.is_empty() => Ok(Section::Group(name.to_string())),
+            Some(("extra", name)) if !name.is_empty() => Ok(Section::Extra(name.to_string())),
+            _ => Err(SectionRuleError::Section(value)),
+        }
+    }
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Section::Runtime => write!(f, "runtime"),
+            Section::Group(name) => write!(f, "group:{name}"),
+            Section::Extra(name) => write!(f, "extra:{name}"),
+        }
+    }
+}
+
+/// Files matching `path`, a glob relative to the project root, have their
+/// imports written to `section`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SectionRule {
+    pub path: String,
+    pub section: Section,
+}
+
+/// A dependency declared in a group or extra that runtime code imports, so it
+/// should be a runtime dependency
+#[derive(Debug, Clone, PartialEq)]
+pub struct Promotion {
+    pub dependency: Dependency,
+    pub section: Section,
+    /// The runtime files importing it
+    pub sources: Vec<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum SectionRuleError {
+    #[error("invalid path pattern {0}: {1}")]
+    Pattern(String, globset::Error),
+    #[error("invalid section {0}, expected runtime, group:<name> or extra:<name>")]
+    Section(String),
+}
+
+/// Tests go to a `test` dependency group and docs to a `docs` extra
+pub fn default_rules() -> Vec<SectionRule> {
+    let test = Section::Group("test".to_string());
+    let docs = Section::Extra("docs".to_string());
+    let docs_paths = ["**/docs/**", "**/doc/**"].map(String::from);
+    default_dev_paths()
+        .into_iter()
+        .map(|path| (path, &test))
+        .chain(docs_paths.into_iter().map(|path| (path, &docs)))
+        .map(|(path, section)| SectionRule {
+            path,
+            section: section.clone(),
+        })
+        .collect()
+}
+
+/// Test files, whose imports go to the dev group when the dependencies aren't
+/// written from provenance
+pub fn default_dev_paths() -> Vec<String> {
+    [
+        "**/tests/**",
+        "**/test/**",
+        "**/test_*.py",
+        "**/*_test.py",
+        "**/conftest.py",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Where dependencies only imported by the dev paths go when the config
+/// doesn't say
+pub fn default_dev_group() -> String {
+    "dev".to_string()
+}
+
+/// Where dependencies only imported under `if TYPE_CHECKING:` go when the
+/// config doesn't say
+pub fn default_type_checking_section() -> Section {
+    Section::Group("dev".to_string())
+}
+
+/// Where dependencies only imported in a `try` catching `ImportError` go when
+/// the config doesn't say
+pub fn default_optional_section() -> Section {
+    Section::Extra("optional".to_string())
+}
+
+/// Move the dependencies only type checkers import to `section`, whichever
+/// sections the files importing them would put them in
+pub fn route_type_checking(
+    by_section: &mut HashMap<Section, HashSet<Dependency>>,
+    detected: &[DetectedDependency],
+    section: &Section,
+) {
+    route(
+        by_section,
+        detected.iter().filter(|d| d.type_checking_only),
+        section,
+    );
+}
+
+/// Move the dependencies the code can do without, only imported with an
+/// `except ImportError:` fallback, to `section`
+pub fn route_optional(
+    by_section: &mut HashMap<Section, HashSet<Dependency>>,
+    detected: &[DetectedDependency],
+    section: &Section,
+) {
+    route(
+        by_section,
+        detected.iter().filter(|d| d.optional_only),
+        section,
+    );
+}
+
+fn route<'d>(
+    by_section: &mut HashMap<Section, HashSet<Dependency>>,
+    detected: impl Iterator<Item = &'d DetectedDependency>,
+    section: &Section,
+) {
+    for d in detected {
+        for deps in by_section.values_mut() {
+            deps.remove(&d.dependency);
+        }
+        by_section
+            .entry(section.clone())
+            .or_default()
+            .insert(d.dependency.clone());
+    }
+    by_section.retain(|_, deps| !deps.is_empty());
+}
+
+/// Decides which section a dependency belongs in from the files that import it
+#[derive(Debug, Clone)]
+pub struct SectionRules {
+    rules: Vec<(GlobMatcher, Section)>,
+}
+
+impl SectionRules {
+    pub fn new(rules: &[SectionRule]) -> Result<Self, SectionRuleError> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                GlobBuilder::new(&rule.path)
+                    .literal_separator(true)
+                    .build()
+                    .map(|glob| (glob.compile_matcher(), rule.section.clone()))
+                    .map_err(|e| SectionRuleError::Pattern(rule.path.clone(), e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SectionRules { rules })
+    }
+
+    /// Send the files matching any of `paths` to `section`, and the rest to
+    /// the runtime dependencies
+    pub fn for_paths(paths: &[String], section: &Section) -> Result<Self, SectionRuleError> {
+        let rules: Vec<SectionRule> = paths
+            .iter()
+            .map(|path| SectionRule {
+                path: path.clone(),
+                section: section.clone(),
+            })
+            .collect();
+        Self::new(&rules)
+    }
+
+    /// The section of the first rule matching `path`, runtime when none do
+    pub fn section_for(&self, path: &Path) -> Section {
+        self.rules
+            .iter()
+            .find(|(glob, _)| glob.is_match(path))
+            .map(|(_, section)| section.clone())
+            .unwrap_or(Section::Runtime)
+    }
+
+    /// Group dependencies by the sections of the files importing them. A
+    /// dependency imported by any runtime file is a runtime dependency,
+    /// otherwise it goes to every section it's imported from
+    pub fn assign(&self, detected: &[DetectedDependency]) -> HashMap<Section, HashSet<Dependency>> {
+        let mut by_section: HashMap<Section, HashSet<Dependency>> = HashMap::new();
+        for d in detected {
+            let sections: HashSet<Section> =
+                d.files().into_iter().map(|f| self.section_for(f)).collect();
+            if sections.is_empty() || sections.contains(&Section::Runtime) {
+                by_section
+                    .entry(Section::Runtime)
+                    .or_default()
+                    .insert(d.dependency.clone());
+                continue;
+            }
+            for section in sections {
+                by_section
+                    .entry(section)
+                    .or_default()
+                    .insert(d.dependency.clone());
+            }
+        }
+        by_section
+    }
+
+    /// The group and extra dependencies of `pyproject` that runtime files
+    /// import, from the sources of the declared dependencies a scan found
+    pub fn promotions(
+        &self,
+        pyproject: &PyProject,
+        declared: &[DetectedDependency],
+    ) -> Vec<Promotion> {
+        let mut promotions = Vec::new();
+        for d in declared {
+            // Type checkers don't need a runtime dependency, nor does code
+            // falling back when the import fails
+            if d.type_checking_only
+                || d.optional_only
+                || pyproject
+                    .deps()
+                    .iter()
+                    .any(|r| d.dependency.same_package(r))
+            {
+                continue;
+            }
+            let runtime_sources: Vec<PathBuf> = d
+                .files()
+                .into_iter()
+                .filter(|f| self.section_for(f) == Section::Runtime)
+                .map(Path::to_path_buf)
+                .collect();
+            if runtime_sources.is_empty() {
+                continue;
+            }
+            for (section, dependency) in pyproject.section_deps() {
+                if *dependency == d.dependency {
+                    promotions.push(Promotion {
+                        dependency: dependency.clone(),
+                        section: section.clone(),
+                        sources: runtime_sources.clone(),
+                    });
+                }
+            }
+        }
+        promotions.sort_by_key(|p| (p.dependency.normalized_name(), p.section.clone()));
+        promotions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::pyproject;
+
+    fn detected(name: &str, sources: &[&str]) -> DetectedDependency {
+        DetectedDependency {
+            dependency: Dependency::parse(name).unwrap(),
+            sources: sources.iter().map(|s| (PathBuf::from(s), 1)).collect(),
+            type_checking_only: false,
+            optional_only: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_section() {
+        assert_eq!(
+            Section::try_from("runtime".to_string()).unwrap(),
+            Section::Runtime
+        );
+        assert_eq!(
+            Section::try_from("group:test".to_string()).unwrap(),
+            Section::Group("test".to_string())
+        );
+        assert_eq!(
+            Section::try_from("extra:docs".to_string()).unwrap(),
+            Section::Extra("docs".to_string())
+        );
+        assert!(Section::try_from("group:".to_string()).is_err());
+        assert!(Section::try_from("dev".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_default_rules() {
+        let rules = SectionRules::new(&default_rules()).unwrap();
+        let test = Section::Group("test".to_string());
+        assert_eq!(rules.section_for(Path::new("tests/test_api.py")), test);
+        assert_eq!(rules.section_for(Path::new("src/app/tests/unit.py")), test);
+        assert_eq!(rules.section_for(Path::new("src/app/test_views.py")), test);
+        assert_eq!(rules.section_for(Path::new("conftest.py")), test);
+        assert_eq!(
+            rules.section_for(Path::new("docs/conf.py")),
+            Section::Extra("docs".to_string())
+        );
+        assert_eq!(
+            rules.section_for(Path::new("src/app/contest.py")),
+            Section::Runtime
+        );
+    }
+
+    #[test]
+    fn test_assign_prefers_runtime() {
+        let rules = SectionRules::new(&default_rules()).unwrap();
+        let by_section = rules.assign(&[
+            detected("requests", &["src/app.py", "tests/test_app.py"]),
+            detected("pytest", &["tests/test_app.py"]),
+            detected("hypothesis", &["tests/test_app.py", "docs/conf.py"]),
+        ]);
+        assert_eq!(
+            by_section[&Section::Runtime],
+            HashSet::from([Dependency::parse("requests").unwrap()])
+        );
+        assert_eq!(
+            by_section[&Section::Group("test".to_string())],
+            HashSet::from([
+                Dependency::parse("pytest").unwrap(),
+                Dependency::parse("hypothesis").unwrap()
+            ])
+        );
+        assert_eq!(
+            by_section[&Section::Extra("docs".to_string())],
+            HashSet::from([Dependency::parse("hypothesis").unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_dev_paths_only_move_test_imports() {
+        let dev = Section::Group(default_dev_group());
+        let rules = SectionRules::for_paths(&default_dev_paths(), &dev).unwrap();
+        let by_section = rules.assign(&[
+            detected("pytest", &["tests/test_api.py", "conftest.py"]),
+            detected("requests", &["app/client.py", "tests/test_client.py"]),
+            detected("sphinx", &["docs/conf.py"]),
+        ]);
+        assert_eq!(
+            by_section,
+            HashMap::from([
+                (dev, HashSet::from([Dependency::parse("pytest").unwrap()])),
+                (
+                    Section::Runtime,
+                    HashSet::from([
+                        Dependency::parse("requests").unwrap(),
+                        Dependency::parse("sphinx").unwrap(),
+                    ])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_routes_type_checking_only_dependencies() {
+        let detected = vec![
+            detected("httpx", &["src/app/api.py"]),
+            DetectedDependency {
+                type_checking_only: true,
+                ..detected("mypy-boto3-s3", &["src/app/storage.py"])
+            },
+        ];
+        let rules = SectionRules::new(&default_rules()).unwrap();
+        let mut by_section = rules.assign(&detected);
+        route_type_checking(&mut by_section, &detected, &default_type_checking_section());
+        assert_eq!(
+            by_section,
+            HashMap::from([
+                (
+                    Section::Runtime,
+                    HashSet::from([Dependency::parse("httpx").unwrap()])
+                ),
+                (
+                    Section::Group("dev".to_string()),
+                    HashSet::from([Dependency::parse("mypy-boto3-s3").unwrap()])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_routes_optional_only_dependencies() {
+        let detected = vec![
+            detected("httpx", &["src/app/api.py"]),
+            DetectedDependency {
+                optional_only: true,
+                ..detected("lxml", &["src/app/parsers.py"])
+            },
+        ];
+        let rules = SectionRules::new(&default_rules()).unwrap();
+        let mut by_section = rules.assign(&detected);
+        route_optional(&mut by_section, &detected, &default_optional_section());
+        assert_eq!(
+            by_section,
+            HashMap::from([
+                (
+                    Section::Runtime,
+                    HashSet::from([Dependency::parse("httpx").unwrap()])
+                ),
+                (
+                    Section::Extra("optional".to_string()),
+                    HashSet::from([Dependency::parse("lxml").unwrap()])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let rules = [SectionRule {
+            path: "tests/[".to_string(),
+            section: Section::Runtime,
+        }];
+        assert!(matches!(
+            SectionRules::new(&rules),
+            Err(SectionRuleError::Pattern(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_promotions() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(
+            br#"
+[project]
+name = "app"
+dependencies = ["django"]
+
+[dependency-groups]
+test = ["pytest>=8", "hypothesis"]
+"#,
+        )
+        .unwrap();
+        let path = file.path().to_path_buf();
+        let rules = SectionRules::new(&default_rules()).unwrap();
+        let declared = [
+            detected("pytest", &["src/app/fixtures.py", "tests/test_app.py"]),
+            detected("hypothesis", &["tests/test_app.py"]),
+            detected("django", &["src/app/views.py"]),
+        ];
+
+        let mut pyproject = pyproject::read(&path).unwrap();
+        let promotions = rules.promotions(&pyproject, &declared);
+        assert_eq!(
+            promotions,
+            vec![Promotion {
+                dependency: Dependency::parse("pytest>=8").unwrap(),
+                section: Section::Group("test".to_string()),
+                sources: vec![PathBuf::from("src/app/fixtures.py")],
+            }]
+        );
+
+        pyproject.promote(&promotions[0].section, &promotions[0].dependency);
+        assert!(rules.promotions(&pyproject, &declared).is_empty());
+        pyproject::write(&path, pyproject, HashMap::new(), &Default::default()).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("\"pytest>=8\""), "{contents}");
+        let pyproject = pyproject::read(&path).unwrap();
+        assert!(pyproject
+            .deps()
+            .contains(&Dependency::parse("pytest").unwrap()));
+        assert_eq!(
+            pyproject.section_deps(),
+            &[(
+                Section::Group("test".to_string()),
+                Dependency::parse("hypothesis").unwrap()
+            )]
+        );
+    }
+}
@@ -0,0 +1,516 @@
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::sync::LazyLock;
+
+static VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    // Adapted from the reference regex in PEP 440, appendix B
+    Regex::new(
+        r"(?ix)^\s*v?
+        (?:(?P<epoch>[0-9]+)!)?
+        (?P<release>[0-9]+(?:\.[0-9]+)*)
+        (?:[-_.]?(?P<pre_l>alpha|beta|preview|pre|rc|a|b|c)[-_.]?(?P<pre_n>[0-9]+)?)?
+        (?:-(?P<post_n1>[0-9]+)|[-_.]?(?P<post_l>post|rev|r)[-_.]?(?P<post_n2>[0-9]+)?)?
+        (?:[-_.]?(?P<dev_l>dev)[-_.]?(?P<dev_n>[0-9]+)?)?
+        (?:\+(?P<local>[a-z0-9]+(?:[-_.][a-z0-9]+)*))?
+        \s*$",
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PreRelease {
+    Alpha,
+    Beta,
+    Rc,
+}
+
+/// A PEP 440 version
+#[derive(Debug, Clone)]
+pub struct Version {
+    epoch: u64,
+    release: Vec<u64>,
+    pre: Option<(PreRelease, u64)>,
+    post: Option<u64>,
+    dev: Option<u64>,
+    local: Option<String>,
+}
+
+impl Version {
+    pub fn parse(input: &str) -> Option<Self> {
+        let caps = VERSION_RE.captures(input)?;
+        let number = |name: &str| caps.name(name).map(|m| m.as_str().parse::<u64>().ok());
+        let epoch = number("epoch").unwrap_or(Some(0))?;
+        let release = caps
+            .name("release")?
+            .as_str()
+            .split('.')
+            .map(|p| p.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let pre = match caps.name("pre_l") {
+            Some(l) => {
+                let kind = match l.as_str().to_lowercase().as_str() {
+                    "a" | "alpha" => PreRelease::Alpha,
+                    "b" | "beta" => PreRelease::Beta,
+                    _ => PreRelease::Rc,
+                };
+                Some((kind, number("pre_n").unwrap_or(Some(0))?))
+            }
+            None => None,
+        };
+        let post = match (caps.name("post_n1"), caps.name("post_l")) {
+            (Some(_), _) => Some(number("post_n1").unwrap()?),
+            (None, Some(_)) => Some(number("post_n2").unwrap_or(Some(0))?),
+            (None, None) => None,
+        };
+        let dev = match caps.name("dev_l") {
+            Some(_) => Some(number("dev_n").unwrap_or(Some(0))?),
+            None => None,
+        };
+        let local = caps.name("local").map(|m| m.as_str().to_lowercase());
+        Some(Version {
+            epoch,
+            release,
+            pre,
+            post,
+            dev,
+            local,
+        })
+    }
+
+    /// Pre-releases and dev releases, e.g. 2.0b1 or 2.0.dev3
+    pub fn is_prerelease(&self) -> bool {
+        self.pre.is_some() || self.dev.is_some()
+    }
+
+    pub fn release(&self) -> &[u64] {
+        &self.release
+    }
+
+    /// The first release after every version starting with the first `len`
+    /// release segments of this one, e.g. 1.4.5 with len 2 gives 1.5
+    fn bump(&self, len: usize) -> Version {
+        let mut release: Vec<u64> = self.release.iter().take(len.max(1)).cloned().collect();
+        while release.len() < len {
+            release.push(0);
+        }
+        if let Some(last) = release.last_mut() {
+            *last += 1;
+        }
+        Version {
+            epoch: self.epoch,
+            release,
+            pre: None,
+            post: None,
+            dev: None,
+            local: None,
+        }
+    }
+
+    // Sort key for the pre/post/dev suffixes following PEP 440's ordering:
+    // X.dev0 < X.a0 < X.rc0 < X < X.post0
+    fn suffix_key(&self) -> (i8, PreRelease, u64, i64, i64) {
+        let (pre_rank, pre_kind, pre_n) = match (self.pre, self.post, self.dev) {
+            (Some((kind, n)), _, _) => (0, kind, n),
+            // A dev release of a final version sorts before its pre-releases
+            (None, None, Some(_)) => (-1, PreRelease::Alpha, 0),
+            (None, _, _) => (1, PreRelease::Alpha, 0),
+        };
+        let post = self.post.map(|p| p as i64).unwrap_or(-1);
+        let dev = self.dev.map(|d| d as i64).unwrap_or(i64::MAX);
+        (pre_rank, pre_kind, pre_n, post, dev)
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.epoch != 0 {
+            write!(f, "{}!", self.epoch)?;
+        }
+        let release = self
+            .release
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>()
+            .join(".");
+        write!(f, "{release}")?;
+        if let Some((kind, n)) = self.pre {
+            let kind = match kind {
+                PreRelease::Alpha => "a",
+                PreRelease::Beta => "b",
+                PreRelease::Rc => "rc",
+            };
+            write!(f, "{kind}{n}")?;
+        }
+        if let Some(post) = self.post {
+            write!(f, ".post{post}")?;
+        }
+        if let Some(dev) = self.dev {
+            write!(f, ".dev{dev}")?;
+        }
+        if let Some(local) = &self.local {
+            write!(f, "+{local}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Trailing zeros don't matter, 1.0 == 1.0.0
+        let len = self.release.len().max(other.release.len());
+        let pad = |r: &[u64]| {
+            let mut r = r.to_vec();
+            r.resize(len, 0);
+            r
+        };
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| pad(&self.release).cmp(&pad(&other.release)))
+            .then_with(|| self.suffix_key().cmp(&other.suffix_key()))
+            .then_with(|| local_key(&self.local).cmp(&local_key(&other.local)))
+    }
+}
+
+// Sort key for a local version label. Segments compare one by one, numbers
+// numerically and after any letters, so 1.0+abc.7 < 1.0+5
+fn local_key(local: &Option<String>) -> Option<Vec<(bool, u64, &str)>> {
+    local.as_ref().map(|l| {
+        l.split(['.', '-', '_'])
+            .map(|segment| match segment.parse::<u64>() {
+                Ok(n) => (true, n, ""),
+                Err(_) => (false, 0, segment),
+            })
+            .collect()
+    })
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// One end of a range of versions, `None` meaning unbounded
+#[derive(Debug, Clone)]
+struct Bound {
+    version: Option<Version>,
+    inclusive: bool,
+}
+
+/// The range of versions a single (specifier, version) pair allows, `None`
+/// when the pair doesn't constrain the range (e.g. `!=`) or can't be parsed
+fn specifier_range(op: &str, version: &str) -> Option<(Bound, Bound)> {
+    let unbounded = || Bound {
+        version: None,
+        inclusive: false,
+    };
+    let bound = |version: Version, inclusive: bool| Bound {
+        version: Some(version),
+        inclusive,
+    };
+    if let Some(prefix) = version.strip_suffix(".*") {
+        // Wildcards only make sense for ==, e.g. ==1.4.* is >=1.4,<1.5
+        let v = Version::parse(prefix)?;
+        let len = v.release.len();
+        return match op {
+            "==" => {
+                let upper = v.bump(len);
+                let lower = Version {
+                    pre: None,
+                    post: None,
+                    dev: None,
+                    local: None,
+                    ..v
+                };
+                Some((bound(lower, true), bound(upper, false)))
+            }
+            _ => None,
+        };
+    }
+    let v = Version::parse(version)?;
+    match op {
+        "==" | "===" => Some((bound(v.clone(), true), bound(v, true))),
+        ">=" => Some((bound(v, true), unbounded())),
+        ">" => Some((bound(v, false), unbounded())),
+        "<=" => Some((unbounded(), bound(v, true))),
+        "<" => Some((unbounded(), bound(v, false))),
+        "~=" => {
+            let len = v.release.len().max(2) - 1;
+            let upper = v.bump(len);
+            Some((bound(v, true), bound(upper, false)))
+        }
+        // Poetry style caret, everything up to the next change in the left-most non-zero segment
+        "^" => {
+            let len = v.release.iter().position(|r| *r != 0).unwrap_or(0) + 1;
+            let upper = v.bump(len);
+            Some((bound(v, true), bound(upper, false)))
+        }
+        _ => None,
+    }
+}
+
+/// Split a specifier set like `>=3.8, <4` into (specifier, version) pairs,
+/// skipping anything that doesn't look like one
+pub fn parse_specifiers(input: &str) -> Vec<(String, String)> {
+    input
+        .split(',')
+        .filter_map(|spec| {
+            let spec = spec.trim();
+            let version_start = spec.find(|c: char| !"~=<>!^".contains(c))?;
+            let (op, version) = spec.split_at(version_start);
+            (!op.is_empty()).then(|| (op.to_string(), version.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The lower bound of a set of (specifier, version) pairs, e.g. 3.9 for
+/// `>=3.9,<4`. None when nothing bounds it from below
+pub fn lowest_allowed(specifiers: &[(String, String)]) -> Option<Version> {
+    specifiers
+        .iter()
+        .filter_map(|(op, version)| specifier_range(op, version)?.0.version)
+        .max()
+}
+
+/// Whether `version` satisfies every one of the (specifier, version) pairs
+pub fn specifiers_allow(specifiers: &[(String, String)], version: &Version) -> bool {
+    specifiers.iter().all(|(op, spec)| {
+        if op == "!=" {
+            // !=3.0.* excludes the range ==3.0.* allows
+            return match spec.ends_with(".*") {
+                true => specifier_range("==", spec).is_none_or(|(low, high)| {
+                    !(low.version.is_some_and(|l| *version >= l)
+                        && high.version.is_some_and(|h| *version < h))
+                }),
+                false => Version::parse(spec).is_none_or(|v| v != *version),
+            };
+        }
+        let Some((low, high)) = specifier_range(op, spec) else {
+            return true;
+        };
+        let above = low.version.as_ref().is_none_or(|l| match low.inclusive {
+            true => version >= l,
+            false => version > l,
+        });
+        let below = high.version.as_ref().is_none_or(|h| match high.inclusive {
+            true => version <= h,
+            false => version < h,
+        });
+        above && below
+    })
+}
+
+/// Whether there is at least one version that satisfies every one of the
+/// (specifier, version) pairs, e.g. `>=2` and `<2` can never both hold
+pub fn specifiers_compatible(specifiers: &[(String, String)]) -> bool {
+    let mut lower = Bound {
+        version: None,
+        inclusive: false,
+    };
+    let mut upper = Bound {
+        version: None,
+        inclusive: false,
+    };
+    for (op, version) in specifiers {
+        let Some((low, high)) = specifier_range(op, version) else {
+            continue;
+        };
+        if let Some(v) = &low.version {
+            let tighter = match &lower.version {
+                None => true,
+                Some(current) => v > current || (v == current && !low.inclusive),
+            };
+            if tighter {
+                lower = low;
+            }
+        }
+        if let Some(v) = &high.version {
+            let tighter = match &upper.version {
+                None => true,
+                Some(current) => v < current || (v == current && !high.inclusive),
+            };
+            if tighter {
+                upper = high;
+            }
+        }
+    }
+    let (Some(low), Some(high)) = (&lower.version, &upper.version) else {
+        return true;
+    };
+    match low.cmp(high) {
+        Ordering::Less => true,
+        Ordering::Equal => {
+            lower.inclusive
+                && upper.inclusive
+                // ==1.0 and !=1.0 exclude the only version left
+                && !specifiers.iter().any(|(op, v)| {
+                    op == "!=" && Version::parse(v).is_some_and(|v| v == *low)
+                })
+        }
+        Ordering::Greater => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(input: &[(&str, &str)]) -> Vec<(String, String)> {
+        input
+            .iter()
+            .map(|(op, v)| (op.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let v = Version::parse("1!2.3.4rc1.post2.dev3+local.7").unwrap();
+        assert_eq!(v.epoch, 1);
+        assert_eq!(v.release, vec![2, 3, 4]);
+        assert_eq!(v.pre, Some((PreRelease::Rc, 1)));
+        assert_eq!(v.post, Some(2));
+        assert_eq!(v.dev, Some(3));
+        assert_eq!(v.local, Some("local.7".to_string()));
+        assert_eq!(v.to_string(), "1!2.3.4rc1.post2.dev3+local.7");
+
+        assert_eq!(Version::parse("v1.0-BETA-2").unwrap().to_string(), "1.0b2");
+        assert_eq!(Version::parse("1.0-1").unwrap().to_string(), "1.0.post1");
+        assert!(Version::parse("not-a-version").is_none());
+        assert!(Version::parse("1.0.*").is_none());
+    }
+
+    #[test]
+    fn test_compare() {
+        assert!(Version::parse("1.10").unwrap() > Version::parse("1.9").unwrap());
+        assert_eq!(
+            Version::parse("1.0").unwrap(),
+            Version::parse("1.0.0").unwrap()
+        );
+        assert!(Version::parse("1.0rc1").unwrap() < Version::parse("1.0").unwrap());
+    }
+
+    #[test]
+    fn test_pep_440_ordering() {
+        // The example ordering from PEP 440's "Summary of permitted suffixes
+        // and relative ordering", each version older than the next
+        let ordered = [
+            "1.dev0",
+            "1.0.dev456",
+            "1.0a1",
+            "1.0a2.dev456",
+            "1.0a12.dev456",
+            "1.0a12",
+            "1.0b1.dev456",
+            "1.0b2",
+            "1.0b2.post345.dev456",
+            "1.0b2.post345",
+            "1.0rc1.dev456",
+            "1.0rc1",
+            "1.0",
+            "1.0+abc.5",
+            "1.0+abc.7",
+            "1.0+5",
+            "1.0.post456.dev34",
+            "1.0.post456",
+            "1.0.15",
+            "1.1.dev1",
+            "1.9",
+            "1.10",
+            "2!0.1",
+        ];
+        for pair in ordered.windows(2) {
+            let (older, newer) = (
+                Version::parse(pair[0]).unwrap(),
+                Version::parse(pair[1]).unwrap(),
+            );
+            assert!(older < newer, "{} < {}", pair[0], pair[1]);
+        }
+        // Normalized spellings are the same version
+        for (a, b) in [
+            ("1.0.0", "1"),
+            ("1.0a1", "1.0.alpha1"),
+            ("1.0c1", "1.0rc1"),
+            ("1.0-1", "1.0.post1"),
+            ("1.0.post", "1.0.post0"),
+            ("v1.0", "1.0"),
+        ] {
+            assert_eq!(Version::parse(a).unwrap(), Version::parse(b).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_specifiers_compatible() {
+        assert!(specifiers_compatible(&specs(&[(">=", "2"), ("<", "3")])));
+        assert!(specifiers_compatible(&specs(&[(">=", "2"), ("<=", "2")])));
+        assert!(specifiers_compatible(&specs(&[
+            ("~=", "2.1"),
+            (">=", "2.5")
+        ])));
+        assert!(specifiers_compatible(&specs(&[
+            ("==", "2.*"),
+            (">=", "2.5")
+        ])));
+        assert!(specifiers_compatible(&specs(&[
+            ("!=", "2.0"),
+            (">=", "2.0")
+        ])));
+        assert!(specifiers_compatible(&specs(&[])));
+
+        assert!(!specifiers_compatible(&specs(&[(">=", "2"), ("<", "2")])));
+        assert!(!specifiers_compatible(&specs(&[
+            ("==", "1.0"),
+            ("==", "1.1")
+        ])));
+        assert!(!specifiers_compatible(&specs(&[
+            ("~=", "2.1"),
+            (">=", "3")
+        ])));
+        assert!(!specifiers_compatible(&specs(&[("==", "2.*"), ("<", "2")])));
+        assert!(!specifiers_compatible(&specs(&[
+            ("==", "1.0"),
+            ("!=", "1.0")
+        ])));
+        assert!(!specifiers_compatible(&specs(&[
+            ("^", "0.3.1"),
+            (">=", "0.4")
+        ])));
+    }
+
+    #[test]
+    fn test_parse_specifiers() {
+        assert_eq!(
+            parse_specifiers(">=3.8, <4,!=3.9.*"),
+            specs(&[(">=", "3.8"), ("<", "4"), ("!=", "3.9.*")])
+        );
+        assert_eq!(parse_specifiers("~=3.10"), specs(&[("~=", "3.10")]));
+        assert_eq!(parse_specifiers("3.8"), specs(&[]));
+        assert_eq!(parse_specifiers(""), specs(&[]));
+    }
+
+    #[test]
+    fn test_specifiers_allow() {
+        let v = |s| Version::parse(s).unwrap();
+        assert_eq!(
+            lowest_allowed(&specs(&[(">=", "3.8"), (">", "3.9"), ("<", "4")])),
+            Some(v("3.9"))
+        );
+        assert_eq!(lowest_allowed(&specs(&[("<", "4")])), None);
+        let python = specs(&[(">=", "2.7"), ("!=", "3.0.*"), ("!=", "3.1.*"), ("<", "4")]);
+        assert!(specifiers_allow(&python, &v("2.7")));
+        assert!(specifiers_allow(&python, &v("3.12")));
+        assert!(!specifiers_allow(&python, &v("3.1")));
+        assert!(!specifiers_allow(&python, &v("3.0.1")));
+        assert!(!specifiers_allow(&python, &v("4.0")));
+        assert!(!specifiers_allow(&specs(&[("!=", "3.8")]), &v("3.8.0")));
+        assert!(specifiers_allow(&specs(&[("~=", "3.8")]), &v("3.11")));
+    }
+}